@@ -0,0 +1,196 @@
+//! Tempo estimation and beat tracking from the onset strength.
+//! https://www.ee.columbia.edu/~dpwe/pubs/Ellis07-beattrack.pdf
+//!
+//! The tempo is the lag with the strongest autocorrelation of the onset strength, weighted towards
+//! the most common tempos around 120 BPM. Beats are then placed by dynamic programming, which
+//! balances between hitting strong onsets and keeping the inter-beat interval close to the tempo.
+
+use crate::onset::OnsetStrength;
+
+/// The range of tempos considered
+const MIN_BPM: f32 = 40.0;
+const MAX_BPM: f32 = 240.0;
+/// The center of the tempo prior
+const PREFERRED_BPM: f32 = 120.0;
+/// The width of the tempo prior in octaves
+const PRIOR_WIDTH_OCTAVES: f32 = 1.0;
+/// How strictly beats follow the tempo, the penalty for deviations of the inter-beat interval
+const TIGHTNESS: f32 = 100.0;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Beats {
+    /// Tempo in beats per minute
+    pub(crate) bpm: f32,
+    /// Beat timestamps in seconds from the beginning of the signal
+    pub(crate) beats: Vec<f32>,
+}
+
+/// Estimates the tempo in beats per minute.
+/// Returns `None` if the signal is too short or has no onsets.
+pub(crate) fn estimate_tempo(strength: &OnsetStrength) -> Option<f32> {
+    let values = &strength.values;
+    let mean = values.iter().sum::<f32>() / values.len().max(1) as f32;
+    let centered: Vec<f32> = values.iter().map(|v| v - mean).collect();
+
+    let min_lag = (60.0 / MAX_BPM / strength.hop_sec).floor().max(1.0) as usize;
+    let max_lag = ((60.0 / MIN_BPM / strength.hop_sec).ceil() as usize).min(centered.len() / 2);
+    if min_lag + 1 >= max_lag {
+        return None;
+    }
+
+    let autocorrelation: Vec<f32> = (0..=max_lag + 1)
+        .map(|lag| {
+            centered
+                .iter()
+                .zip(&centered[lag.min(centered.len())..])
+                .map(|(a, b)| a * b)
+                .sum()
+        })
+        .collect();
+
+    let lag_to_bpm = |lag: f32| 60.0 / (lag * strength.hop_sec);
+    let weighted = |lag: usize| {
+        let octaves = (lag_to_bpm(lag as f32) / PREFERRED_BPM).log2() / PRIOR_WIDTH_OCTAVES;
+        autocorrelation[lag] * (-0.5 * octaves * octaves).exp()
+    };
+    let best = (min_lag..=max_lag).max_by(|a, b| weighted(*a).total_cmp(&weighted(*b)))?;
+    if autocorrelation[best] <= 0.0 {
+        return None;
+    }
+
+    // Parabolic interpolation around the peak for the sub-frame precision
+    let (prev, peak, next) = (
+        autocorrelation[best - 1],
+        autocorrelation[best],
+        autocorrelation[best + 1],
+    );
+    let denominator = prev - 2.0 * peak + next;
+    let shift = if denominator < 0.0 {
+        (0.5 * (prev - next) / denominator).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    Some(lag_to_bpm(best as f32 + shift))
+}
+
+/// Places beats with the given tempo on the strongest onsets
+pub(crate) fn track_beats(strength: &OnsetStrength, bpm: f32) -> Vec<f32> {
+    let values = &strength.values;
+    if values.is_empty() || bpm <= 0.0 {
+        return vec![];
+    }
+
+    // Normalize the onset strength, so the tightness doesn't depend on its scale
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let std = (values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32).sqrt();
+    let onsets: Vec<f32> = values.iter().map(|v| v / std.max(f32::EPSILON)).collect();
+
+    let period = 60.0 / bpm / strength.hop_sec;
+    let mut score = onsets.clone();
+    let mut backlink = vec![None; onsets.len()];
+    for t in 0..onsets.len() {
+        let from = t.saturating_sub((2.0 * period).round() as usize);
+        let to = t.saturating_sub((period / 2.0).round() as usize);
+        let best = (from..to)
+            .map(|prev| {
+                let deviation = ((t - prev) as f32 / period).ln();
+                (prev, score[prev] - TIGHTNESS * deviation * deviation)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((prev, prev_score)) = best {
+            score[t] += prev_score;
+            backlink[t] = Some(prev);
+        }
+    }
+
+    // The last beat is the best one within the last period, then follow links back
+    let last_period = onsets.len().saturating_sub(period.round() as usize);
+    let Some(mut beat) = (last_period..onsets.len()).max_by(|a, b| score[*a].total_cmp(&score[*b]))
+    else {
+        return vec![];
+    };
+    let mut beats = vec![beat];
+    while let Some(prev) = backlink[beat] {
+        beats.push(prev);
+        beat = prev;
+    }
+    beats.reverse();
+
+    beats
+        .into_iter()
+        .map(|frame| frame as f32 * strength.hop_sec)
+        .collect()
+}
+
+/// Estimates the tempo and tracks beats
+pub(crate) fn beats(strength: &OnsetStrength) -> Option<Beats> {
+    let bpm = estimate_tempo(strength)?;
+    Some(Beats {
+        bpm,
+        beats: track_beats(strength, bpm),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Onset strength with pulses every `period_sec` starting at `phase_sec` and some weaker off-beat pulses
+    fn pulses(hop_sec: f32, duration_sec: f32, period_sec: f32, phase_sec: f32) -> OnsetStrength {
+        let mut values = vec![0.1; (duration_sec / hop_sec) as usize];
+        let mut time = phase_sec;
+        while time < duration_sec {
+            let frame = (time / hop_sec).round() as usize;
+            if let Some(value) = values.get_mut(frame) {
+                *value = 1.0;
+            }
+            // an off-beat onset
+            let frame = ((time + period_sec / 3.0) / hop_sec).round() as usize;
+            if let Some(value) = values.get_mut(frame) {
+                *value = 0.4;
+            }
+            time += period_sec;
+        }
+        OnsetStrength { values, hop_sec }
+    }
+
+    #[test]
+    fn estimate_tempo_test() {
+        assert_eq!(
+            estimate_tempo(&OnsetStrength {
+                values: vec![],
+                hop_sec: 0.01
+            }),
+            None
+        );
+        assert_eq!(
+            estimate_tempo(&OnsetStrength {
+                values: vec![1.0; 1000],
+                hop_sec: 0.01
+            }),
+            None
+        );
+
+        for bpm in [70.0, 100.0, 128.0, 150.0] {
+            let strength = pulses(0.01, 30.0, 60.0 / bpm, 0.3);
+            let estimated = estimate_tempo(&strength).unwrap();
+            assert!((estimated - bpm).abs() < 2.0, "{estimated} vs {bpm}");
+        }
+    }
+
+    #[test]
+    fn track_beats_test() {
+        let hop_sec = 0.01;
+        let period_sec = 0.5;
+        let phase_sec = 0.23;
+        let strength = pulses(hop_sec, 20.0, period_sec, phase_sec);
+
+        let beats = beats(&strength).unwrap();
+        assert!((beats.bpm - 120.0).abs() < 2.0, "{}", beats.bpm);
+        assert_eq!(beats.beats.len(), 40);
+        for (i, beat) in beats.beats.iter().enumerate() {
+            let expected = phase_sec + i as f32 * period_sec;
+            assert!((beat - expected).abs() <= hop_sec, "{beat} vs {expected}");
+        }
+    }
+}
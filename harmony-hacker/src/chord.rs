@@ -0,0 +1,384 @@
+//! Chord recognition with chroma template matching and HMM smoothing.
+//! https://www.music.mcgill.ca/~ich/research/chord_recognition/
+//!
+//! Each frame's chroma is compared with binary templates of all chords (cosine similarity).
+//! The raw frame-by-frame decision flickers a lot, so the sequence is smoothed with Viterbi decoding
+//! of a hidden Markov model where chords tend to stay the same between frames.
+
+use std::io::Write;
+
+use crate::{
+    chroma::{self, pitch_class, pitch_class_name},
+    notes::KEYS_COUNT,
+};
+
+/// Probability of the chord to stay the same in the next frame
+const SELF_TRANSITION: f32 = 0.95;
+/// Scale of the similarity in the log-likelihood, the higher the less smoothing
+const SHARPNESS: f32 = 20.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Quality {
+    Major,
+    Minor,
+    Dominant7,
+    Major7,
+    Minor7,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+}
+
+impl Quality {
+    const ALL: [Quality; 9] = [
+        Quality::Major,
+        Quality::Minor,
+        Quality::Dominant7,
+        Quality::Major7,
+        Quality::Minor7,
+        Quality::Diminished,
+        Quality::Augmented,
+        Quality::Sus2,
+        Quality::Sus4,
+    ];
+
+    /// Intervals of chord tones in semitones from the root
+    fn intervals(self) -> &'static [usize] {
+        match self {
+            Quality::Major => &[0, 4, 7],
+            Quality::Minor => &[0, 3, 7],
+            Quality::Dominant7 => &[0, 4, 7, 10],
+            Quality::Major7 => &[0, 4, 7, 11],
+            Quality::Minor7 => &[0, 3, 7, 10],
+            Quality::Diminished => &[0, 3, 6],
+            Quality::Augmented => &[0, 4, 8],
+            Quality::Sus2 => &[0, 2, 7],
+            Quality::Sus4 => &[0, 5, 7],
+        }
+    }
+
+    /// Suffix for lead sheets, e.g. "m7" in "Am7"
+    fn symbol(self) -> &'static str {
+        match self {
+            Quality::Major => "",
+            Quality::Minor => "m",
+            Quality::Dominant7 => "7",
+            Quality::Major7 => "maj7",
+            Quality::Minor7 => "m7",
+            Quality::Diminished => "dim",
+            Quality::Augmented => "aug",
+            Quality::Sus2 => "sus2",
+            Quality::Sus4 => "sus4",
+        }
+    }
+
+    /// Shorthand in Harte's syntax used by `.lab` files, e.g. "min7" in "A:min7"
+    fn shorthand(self) -> &'static str {
+        match self {
+            Quality::Major => "maj",
+            Quality::Minor => "min",
+            Quality::Dominant7 => "7",
+            Quality::Major7 => "maj7",
+            Quality::Minor7 => "min7",
+            Quality::Diminished => "dim",
+            Quality::Augmented => "aug",
+            Quality::Sus2 => "sus2",
+            Quality::Sus4 => "sus4",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Chord {
+    /// Pitch class of the root, 0 is C
+    pub(crate) root: usize,
+    pub(crate) quality: Quality,
+    /// Pitch class of the lowest note if it is not the root, i.e. the chord is inverted
+    pub(crate) bass: Option<usize>,
+}
+
+impl Chord {
+    /// Lead sheet symbol like "C", "Am7" or "G/B"
+    pub(crate) fn symbol(&self, flats: bool) -> String {
+        let mut symbol = format!(
+            "{}{}",
+            pitch_class_name(self.root, flats),
+            self.quality.symbol()
+        );
+        if let Some(bass) = self.bass {
+            symbol.push('/');
+            symbol.push_str(pitch_class_name(bass, flats));
+        }
+        symbol
+    }
+
+    /// Label in Harte's syntax like "C:maj", "A:min7" or "G:maj/3"
+    pub(crate) fn lab_label(&self) -> String {
+        let mut label = format!(
+            "{}:{}",
+            pitch_class_name(self.root, false),
+            self.quality.shorthand()
+        );
+        if let Some(bass) = self.bass {
+            const DEGREES: [&str; 12] = [
+                "1", "b2", "2", "b3", "3", "4", "b5", "5", "#5", "6", "b7", "7",
+            ];
+            label.push('/');
+            label.push_str(DEGREES[(bass + 12 - self.root) % 12]);
+        }
+        label
+    }
+}
+
+/// A chord (or no chord) lasting for a time range
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ChordSegment {
+    pub(crate) start_sec: f32,
+    pub(crate) end_sec: f32,
+    pub(crate) chord: Option<Chord>,
+}
+
+/// HMM states are all (root, quality) pairs and no chord as the last one
+fn states() -> Vec<Option<(usize, Quality)>> {
+    (0..12)
+        .flat_map(|root| Quality::ALL.map(|quality| Some((root, quality))))
+        .chain(std::iter::once(None))
+        .collect()
+}
+
+/// Cosine similarity between the chroma and the binary template of the state.
+/// No chord is modelled as the flat template, which matches noise.
+fn similarity(chroma: &chroma::Chroma, state: Option<(usize, Quality)>) -> f32 {
+    let mut template = [0.0; 12];
+    match state {
+        Some((root, quality)) => {
+            for interval in quality.intervals() {
+                template[(root + interval) % 12] = 1.0;
+            }
+        }
+        None => template = [1.0; 12],
+    }
+
+    let dot: f32 = chroma.iter().zip(&template).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32; 12]| v.iter().map(|v| v * v).sum::<f32>().sqrt();
+    dot / (norm(chroma) * norm(&template)).max(f32::EPSILON)
+}
+
+/// Recognizes chords in the per-frame key activations.
+///
+/// Frames where no key reaches `threshold` are treated as silence without chords.
+/// The bass note of each segment is the lowest key above the threshold found most often in it.
+pub(crate) fn recognize_chords(
+    frames: &[[f32; KEYS_COUNT]],
+    start_sec: f32,
+    frame_duration_sec: f32,
+    threshold: f32,
+) -> Vec<ChordSegment> {
+    let states = states();
+    let no_chord = states.len() - 1;
+    if frames.is_empty() {
+        return vec![];
+    }
+
+    // log-likelihoods of each state in each frame
+    let emissions: Vec<Vec<f32>> = frames
+        .iter()
+        .map(|frame| {
+            let silent = frame.iter().all(|activation| *activation < threshold);
+            let chroma = chroma::normalize(chroma::chroma(frame));
+            states
+                .iter()
+                .enumerate()
+                .map(|(i, state)| match silent {
+                    true if i == no_chord => 0.0,
+                    true => f32::NEG_INFINITY,
+                    false => SHARPNESS * similarity(&chroma, *state),
+                })
+                .collect()
+        })
+        .collect();
+
+    // Viterbi decoding with the uniform probability to change the chord
+    let stay = SELF_TRANSITION.ln();
+    let change = ((1.0 - SELF_TRANSITION) / (states.len() - 1) as f32).ln();
+    let mut scores = emissions[0].clone();
+    let mut backlinks = vec![vec![0; states.len()]; frames.len()];
+    for (t, emission) in emissions.iter().enumerate().skip(1) {
+        let (best_prev, best_score) = scores
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        scores = scores
+            .iter()
+            .enumerate()
+            .map(|(state, score)| {
+                let (prev, score) = if score + stay >= best_score + change {
+                    (state, score + stay)
+                } else {
+                    (best_prev, best_score + change)
+                };
+                backlinks[t][state] = prev;
+                score + emission[state]
+            })
+            .collect();
+    }
+
+    let mut path = vec![0; frames.len()];
+    path[frames.len() - 1] = scores
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(state, _)| state)
+        .unwrap();
+    for t in (1..frames.len()).rev() {
+        path[t - 1] = backlinks[t][path[t]];
+    }
+
+    // Merge consecutive frames with the same state into segments
+    let mut segments = Vec::new();
+    let mut from = 0;
+    for to in 1..=frames.len() {
+        if to < frames.len() && path[to] == path[from] {
+            continue;
+        }
+        let chord = states[path[from]].map(|(root, quality)| Chord {
+            root,
+            quality,
+            bass: bass_note(&frames[from..to], threshold).filter(|bass| *bass != root),
+        });
+        segments.push(ChordSegment {
+            start_sec: start_sec + from as f32 * frame_duration_sec,
+            end_sec: start_sec + to as f32 * frame_duration_sec,
+            chord,
+        });
+        from = to;
+    }
+    segments
+}
+
+/// The pitch class of the most common lowest sounding key
+fn bass_note(frames: &[[f32; KEYS_COUNT]], threshold: f32) -> Option<usize> {
+    let mut counts = [0; 12];
+    for frame in frames {
+        if let Some(key) = frame.iter().position(|activation| *activation >= threshold) {
+            counts[pitch_class(key as u8)] += 1;
+        }
+    }
+    let (pitch_class, count) = counts.iter().enumerate().max_by_key(|(_, count)| **count)?;
+    (*count > 0).then_some(pitch_class)
+}
+
+/// Writes segments in the `.lab` format: start and end in seconds and the chord label per line
+pub(crate) fn write_lab(mut writer: impl Write, segments: &[ChordSegment]) -> std::io::Result<()> {
+    for segment in segments {
+        let label = segment
+            .chord
+            .map_or_else(|| "N".to_owned(), |chord| chord.lab_label());
+        writeln!(
+            writer,
+            "{:.3}\t{:.3}\t{label}",
+            segment.start_sec, segment.end_sec
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn chord_labels_test() {
+        let chord = |root, quality, bass| Chord {
+            root,
+            quality,
+            bass,
+        };
+        assert_eq!(chord(0, Quality::Major, None).symbol(false), "C");
+        assert_eq!(chord(9, Quality::Minor7, None).symbol(false), "Am7");
+        assert_eq!(chord(7, Quality::Major, Some(11)).symbol(false), "G/B");
+        assert_eq!(chord(10, Quality::Dominant7, None).symbol(true), "Bb7");
+
+        assert_eq!(chord(0, Quality::Major, None).lab_label(), "C:maj");
+        assert_eq!(chord(9, Quality::Minor7, None).lab_label(), "A:min7");
+        assert_eq!(chord(7, Quality::Major, Some(11)).lab_label(), "G:maj/3");
+        assert_eq!(chord(2, Quality::Sus4, Some(9)).lab_label(), "D:sus4/5");
+    }
+
+    #[test]
+    fn recognize_chords_test() {
+        assert_eq!(recognize_chords(&[], 0.0, 0.1, 0.5), vec![]);
+
+        let chord_frame = |keys: &[usize]| {
+            let mut frame = [0.0; KEYS_COUNT];
+            for key in keys {
+                frame[*key] = 1.0;
+            }
+            frame
+        };
+        // C4 E4 G4, then A3 C4 E4 G4 with a single noisy frame in the middle, then E3 G3 C4 and silence
+        let c_major = chord_frame(&[39, 43, 46]);
+        let a_minor7 = chord_frame(&[36, 39, 43, 46]);
+        let noisy = chord_frame(&[36, 40, 43, 47]);
+        let c_major_6 = chord_frame(&[31, 34, 39]);
+        let silence = chord_frame(&[]);
+
+        let mut frames = vec![c_major; 10];
+        frames.extend([a_minor7; 5]);
+        frames.push(noisy);
+        frames.extend([a_minor7; 4]);
+        frames.extend([c_major_6; 10]);
+        frames.extend([silence; 5]);
+
+        let segments = recognize_chords(&frames, 1.0, 0.5, 0.5);
+        let segment = |start_sec, end_sec, chord| ChordSegment {
+            start_sec,
+            end_sec,
+            chord,
+        };
+        assert_eq!(
+            segments,
+            vec![
+                segment(
+                    1.0,
+                    6.0,
+                    Some(Chord {
+                        root: 0,
+                        quality: Quality::Major,
+                        bass: None
+                    })
+                ),
+                segment(
+                    6.0,
+                    11.0,
+                    Some(Chord {
+                        root: 9,
+                        quality: Quality::Minor7,
+                        bass: None
+                    })
+                ),
+                segment(
+                    11.0,
+                    16.0,
+                    Some(Chord {
+                        root: 0,
+                        quality: Quality::Major,
+                        bass: Some(4)
+                    })
+                ),
+                segment(16.0, 18.5, None),
+            ]
+        );
+
+        let mut lab = Vec::new();
+        write_lab(&mut lab, &segments).unwrap();
+        assert_eq!(
+            String::from_utf8(lab).unwrap(),
+            "1.000\t6.000\tC:maj\n6.000\t11.000\tA:min7\n11.000\t16.000\tC:maj/3\n16.000\t18.500\tN\n"
+        );
+    }
+}
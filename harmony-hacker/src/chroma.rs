@@ -0,0 +1,108 @@
+//! Chroma features: energy of each of 12 pitch classes regardless of the octave.
+
+use crate::notes::KEYS_COUNT;
+
+/// Energies of pitch classes, starting from C
+pub(crate) type Chroma = [f32; 12];
+
+/// The pitch class (0 is C, 11 is B) of the key number 0..88
+pub(crate) fn pitch_class(key: u8) -> usize {
+    // The first key is A0
+    (key as usize + 9) % 12
+}
+
+/// The name of the pitch class (0 is C), black keys are spelled with flats or sharps
+pub(crate) fn pitch_class_name(pitch_class: usize, flats: bool) -> &'static str {
+    const SHARP_NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+    const FLAT_NAMES: [&str; 12] = [
+        "C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B",
+    ];
+    if flats {
+        FLAT_NAMES[pitch_class % 12]
+    } else {
+        SHARP_NAMES[pitch_class % 12]
+    }
+}
+
+/// The scientific pitch name of the key number 0..88, e.g. A0 or C#4
+pub(crate) fn key_name(key: u8) -> String {
+    let octave = (key as usize + 9) / 12;
+    format!("{}{octave}", pitch_class_name(pitch_class(key), false))
+}
+
+/// Folds energies of 88 keys into 12 pitch classes
+pub(crate) fn chroma(keys: &[f32; KEYS_COUNT]) -> Chroma {
+    let mut chroma = [0.0; 12];
+    for (key, energy) in keys.iter().enumerate() {
+        chroma[pitch_class(key as u8)] += energy;
+    }
+    chroma
+}
+
+/// Sums chroma of all frames, each normalized to the unit max so loud frames don't dominate
+pub(crate) fn summed_chroma(frames: &[[f32; KEYS_COUNT]]) -> Chroma {
+    let mut sum = [0.0; 12];
+    for frame in frames {
+        let chroma = normalize(chroma(frame));
+        for (sum, value) in sum.iter_mut().zip(chroma) {
+            *sum += value;
+        }
+    }
+    sum
+}
+
+/// Scales chroma to the unit max value, silence stays silence
+pub(crate) fn normalize(chroma: Chroma) -> Chroma {
+    let max = chroma.iter().copied().fold(0.0, f32::max);
+    if max > 0.0 {
+        chroma.map(|value| value / max)
+    } else {
+        chroma
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn chroma_test() {
+        assert_eq!(pitch_class(0), 9); // A0
+        assert_eq!(pitch_class(3), 0); // C1
+        assert_eq!(pitch_class(39), 0); // C4
+        assert_eq!(pitch_class(87), 0); // C8
+        assert_eq!(pitch_class_name(9, false), "A");
+        assert_eq!(pitch_class_name(1, false), "C#");
+        assert_eq!(pitch_class_name(1, true), "Db");
+        assert_eq!(key_name(0), "A0");
+        assert_eq!(key_name(2), "B0");
+        assert_eq!(key_name(3), "C1");
+        assert_eq!(key_name(40), "C#4");
+        assert_eq!(key_name(48), "A4");
+        assert_eq!(key_name(87), "C8");
+
+        let mut keys = [0.0; KEYS_COUNT];
+        keys[39] = 1.0; // C4
+        keys[51] = 0.5; // C5
+        keys[43] = 0.25; // E4
+        keys[0] = 2.0; // A0
+        let chroma = chroma(&keys);
+        assert_eq!(
+            chroma,
+            [1.5, 0.0, 0.0, 0.0, 0.25, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            normalize(chroma),
+            [0.75, 0.0, 0.0, 0.0, 0.125, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]
+        );
+        assert_eq!(normalize([0.0; 12]), [0.0; 12]);
+
+        assert_eq!(
+            summed_chroma(&[keys, keys, [0.0; KEYS_COUNT]]),
+            [1.5, 0.0, 0.0, 0.0, 0.25, 0.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0]
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! Per-key detuning report: how far each key of the recorded piano is from the configured tuning.
+//!
+//! Meant for a recording of each key struck in turn (or a chromatic scale), where every transcribed
+//! note is a single key. The report is shown as a bar chart above the keyboard and exported as CSV.
+//! The first partial of each key is also followed through its note with the phase vocoder, which
+//! gives a steadier frequency and the beating of unison strings.
+
+use std::io::Write;
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    chroma,
+    goertzel::{self, TrackedBlock},
+    inharmonicity::PartialsEstimate,
+    key_to_keyboard_pos_x,
+    notes::KEYS_COUNT,
+    Keyboard, KEYBOARD_SIZE,
+};
+
+/// The height of the chart above the keyboard, half of it is for sharp keys and half for flat ones
+const CHART_HEIGHT: f32 = 80.0;
+/// The deviation that fills the half of the chart
+const CHART_RANGE_CENTS: f32 = 50.0;
+/// Deviations within this range are fine for the tuner, larger ones need attention
+const TOLERANCE_CENTS: f32 = 2.0;
+const WARNING_CENTS: f32 = 5.0;
+/// Blocks the first partial is tracked in. Unison strings closer than the 10 Hz resolution share
+/// the block and beat in its power, which is sampled fast enough for beats up to a few Hz.
+const TRACK_BLOCK_SEC: f32 = 0.1;
+const TRACK_HOP_SEC: f32 = 0.02;
+/// The power of beating strings varies more than this around the decay of the note
+const MIN_BEATING_DB: f32 = 1.0;
+
+/// Measurement of a single key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct KeyDetuning {
+    /// The frequency the key should sound at in the configured tuning
+    pub(crate) expected_hz: f32,
+    /// The measured frequency of the first partial
+    pub(crate) measured_hz: f32,
+    /// The deviation of the measured frequency from the expected one
+    pub(crate) cents: f32,
+    /// The measured inharmonicity coefficient
+    pub(crate) inharmonicity: f32,
+    /// The median instantaneous frequency of the first partial, if the note is long enough
+    pub(crate) tracked_hz: Option<f32>,
+    /// The rate of beating of unison strings, `None` if the power only decays
+    pub(crate) beat_hz: Option<f32>,
+}
+
+#[derive(Default, Resource)]
+pub(crate) struct DetuningReport {
+    /// Measurements of keys, `None` for keys that weren't played
+    pub(crate) keys: Vec<Option<KeyDetuning>>,
+}
+
+impl DetuningReport {
+    /// Builds the report from partials of notes. If a key was played several times, the note with
+    /// the most partials found is used as the most reliable one.
+    pub(crate) fn new(
+        estimates: &[(u8, PartialsEstimate)],
+        expected_hz: impl Fn(u8) -> f32,
+    ) -> Self {
+        let mut best: [Option<&PartialsEstimate>; KEYS_COUNT] = [None; KEYS_COUNT];
+        for (key, estimate) in estimates {
+            let best = &mut best[*key as usize];
+            if best.is_none_or(|best| estimate.partials > best.partials) {
+                *best = Some(estimate);
+            }
+        }
+
+        let keys = best
+            .iter()
+            .enumerate()
+            .map(|(key, estimate)| {
+                estimate.map(|estimate| {
+                    let expected_hz = expected_hz(key as u8);
+                    KeyDetuning {
+                        expected_hz,
+                        measured_hz: estimate.first_partial_hz,
+                        cents: 1200.0 * (estimate.first_partial_hz / expected_hz).log2(),
+                        inharmonicity: estimate.coefficient,
+                        tracked_hz: None,
+                        beat_hz: None,
+                    }
+                })
+            })
+            .collect();
+        Self { keys }
+    }
+
+    /// The number of measured keys
+    pub(crate) fn measured(&self) -> usize {
+        self.keys.iter().flatten().count()
+    }
+
+    /// Tracks the first partial of each measured key through the longest of its `notes`
+    pub(crate) fn track_keys(&mut self, notes: &[(u8, &[f32])], sample_rate: u32) {
+        let block_size = (TRACK_BLOCK_SEC * sample_rate as f32) as usize;
+        let hop = (TRACK_HOP_SEC * sample_rate as f32) as usize;
+        for (key, detuning) in self.keys.iter_mut().enumerate() {
+            let Some(detuning) = detuning else {
+                continue;
+            };
+            let Some(samples) = notes
+                .iter()
+                .filter(|(note_key, _)| *note_key as usize == key)
+                .map(|(_, samples)| *samples)
+                .max_by_key(|samples| samples.len())
+            else {
+                continue;
+            };
+            let blocks =
+                goertzel::track(samples, sample_rate, detuning.measured_hz, block_size, hop);
+            // the first block has no frequency of its own
+            let Some(tracked) = blocks.get(1..).filter(|tracked| tracked.len() >= 2) else {
+                continue;
+            };
+            let mut frequencies: Vec<f32> =
+                tracked.iter().map(|block| block.frequency_hz).collect();
+            frequencies.sort_by(f32::total_cmp);
+            detuning.tracked_hz = Some(frequencies[frequencies.len() / 2]);
+            detuning.beat_hz = beat_hz(&blocks, TRACK_HOP_SEC);
+        }
+    }
+}
+
+/// The beating rate from the power of blocks `hop_sec` apart. The linear decay in dB is removed,
+/// and each beat crosses the remaining line twice.
+fn beat_hz(blocks: &[TrackedBlock], hop_sec: f32) -> Option<f32> {
+    if blocks.len() < 3 {
+        return None;
+    }
+    // the least squares line through the power
+    let n = blocks.len() as f32;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = blocks.iter().map(|block| block.power_db).sum::<f32>() / n;
+    let (covariance, variance) =
+        blocks
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(c, v), (x, block)| {
+                let dx = x as f32 - mean_x;
+                (c + dx * (block.power_db - mean_y), v + dx * dx)
+            });
+    let slope = covariance / variance;
+    let residuals: Vec<f32> = blocks
+        .iter()
+        .enumerate()
+        .map(|(x, block)| block.power_db - mean_y - slope * (x as f32 - mean_x))
+        .collect();
+
+    let rms = (residuals.iter().map(|r| r * r).sum::<f32>() / n).sqrt();
+    if rms < MIN_BEATING_DB {
+        return None;
+    }
+    let crossings = residuals
+        .windows(2)
+        .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+        .count();
+    Some(crossings as f32 / 2.0 / ((n - 1.0) * hop_sec))
+}
+
+/// Writes the report as CSV with a row per key, fields of keys that weren't played are empty
+pub(crate) fn write_csv(mut writer: impl Write, report: &DetuningReport) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "key,note,expected_hz,measured_hz,deviation_cents,inharmonicity,tracked_hz,beat_hz"
+    )?;
+    for key in 0..KEYS_COUNT {
+        let name = chroma::key_name(key as u8);
+        match report.keys.get(key).copied().flatten() {
+            Some(detuning) => writeln!(
+                writer,
+                "{},{name},{:.3},{:.3},{:.1},{:.3e},{},{}",
+                key + 1,
+                detuning.expected_hz,
+                detuning.measured_hz,
+                detuning.cents,
+                detuning.inharmonicity,
+                detuning
+                    .tracked_hz
+                    .map_or(String::new(), |hz| format!("{hz:.3}")),
+                detuning
+                    .beat_hz
+                    .map_or(String::new(), |hz| format!("{hz:.2}")),
+            )?,
+            None => writeln!(writer, "{},{name},,,,,,", key + 1)?,
+        }
+    }
+    Ok(())
+}
+
+/// Draws deviations of measured keys as bars above the keyboard, sharp keys up and flat ones down.
+/// Keys with beating unison strings are marked with a dot at the baseline.
+pub(crate) fn detuning_chart_ui(
+    mut contexts: EguiContexts,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    keyboard: Query<&Transform, With<Keyboard>>,
+    report: Res<DetuningReport>,
+) {
+    if report.measured() == 0 {
+        return;
+    }
+    let Ok(keyboard) = keyboard.get_single() else {
+        return;
+    };
+    let window = windows.single();
+    // from the world coordinates with (0,0) in the center to egui's with (0,0) in the top left corner
+    let to_screen =
+        |x: f32, y: f32| egui::Pos2::new(x + window.width() / 2.0, window.height() / 2.0 - y);
+    let left = keyboard.translation.x - KEYBOARD_SIZE.x / 2.0;
+    let baseline = keyboard.translation.y + KEYBOARD_SIZE.y / 2.0 + CHART_HEIGHT / 2.0;
+
+    let painter = contexts
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    painter.rect_filled(
+        egui::Rect::from_two_pos(
+            to_screen(left, baseline - CHART_HEIGHT / 2.0),
+            to_screen(left + KEYBOARD_SIZE.x, baseline + CHART_HEIGHT / 2.0),
+        ),
+        0.0,
+        egui::Color32::from_black_alpha(160),
+    );
+    painter.line_segment(
+        [
+            to_screen(left, baseline),
+            to_screen(left + KEYBOARD_SIZE.x, baseline),
+        ],
+        egui::Stroke::new(1.0, egui::Color32::GRAY),
+    );
+
+    for (key, detuning) in report.keys.iter().enumerate() {
+        let Some(detuning) = detuning else {
+            continue;
+        };
+        let x = keyboard.translation.x + key_to_keyboard_pos_x(key as u8);
+        let cents = detuning.cents.clamp(-CHART_RANGE_CENTS, CHART_RANGE_CENTS);
+        let height = cents / CHART_RANGE_CENTS * CHART_HEIGHT / 2.0;
+        let color = match detuning.cents.abs() {
+            cents if cents <= TOLERANCE_CENTS => egui::Color32::GREEN,
+            cents if cents <= WARNING_CENTS => egui::Color32::YELLOW,
+            _ => egui::Color32::RED,
+        };
+        painter.rect_filled(
+            egui::Rect::from_two_pos(
+                to_screen(x - 4.0, baseline),
+                to_screen(x + 4.0, baseline + height),
+            ),
+            0.0,
+            color,
+        );
+        if detuning.beat_hz.is_some() {
+            painter.circle_filled(to_screen(x, baseline), 2.0, egui::Color32::WHITE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn report_test() {
+        let estimate = |first_partial_hz, partials| PartialsEstimate {
+            first_partial_hz,
+            coefficient: 4e-4,
+            partials,
+        };
+        let report = DetuningReport::new(
+            &[
+                (48, estimate(441.0, 5)),
+                // the more reliable measurement of the same key wins
+                (48, estimate(442.0, 10)),
+                (48, estimate(443.0, 3)),
+                (0, estimate(27.5, 4)),
+            ],
+            |key| 440.0 * 2.0f32.powf((key as f32 - 48.0) / 12.0),
+        );
+        assert_eq!(report.measured(), 2);
+        let a4 = report.keys[48].unwrap();
+        assert_eq!(a4.measured_hz, 442.0);
+        assert!((a4.cents - 7.85).abs() < 0.01, "{}", a4.cents);
+        assert_eq!(report.keys[0].unwrap().cents, 0.0);
+
+        let mut output = Vec::new();
+        write_csv(&mut output, &report).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 89);
+        assert_eq!(
+            lines[0],
+            "key,note,expected_hz,measured_hz,deviation_cents,inharmonicity,tracked_hz,beat_hz"
+        );
+        assert_eq!(lines[1], "1,A0,27.500,27.500,0.0,4.000e-4,,");
+        assert_eq!(lines[2], "2,A#0,,,,,,");
+        assert_eq!(lines[49], "49,A4,440.000,442.000,7.9,4.000e-4,,");
+        assert_eq!(lines[88], "88,C8,,,,,,");
+    }
+
+    #[test]
+    fn track_keys_test() {
+        let sample_rate = 8000;
+        let sine = |hz: f32, i: usize| (2.0 * PI * hz * i as f32 / sample_rate as f32).sin();
+        // a decaying single string and two unison strings beating at 1 Hz
+        let single: Vec<f32> = (0..16000)
+            .map(|i| (-(i as f32) / 8000.0).exp() * sine(440.3, i))
+            .collect();
+        let unison: Vec<f32> = (0..16000)
+            .map(|i| sine(523.0, i) + 0.8 * sine(524.0, i))
+            .collect();
+        let estimate = |first_partial_hz| PartialsEstimate {
+            first_partial_hz,
+            coefficient: 4e-4,
+            partials: 8,
+        };
+        let mut report = DetuningReport::new(
+            &[
+                (48, estimate(440.0)),
+                (51, estimate(523.4)),
+                (0, estimate(27.5)),
+            ],
+            |key| 440.0 * 2.0f32.powf((key as f32 - 48.0) / 12.0),
+        );
+        report.track_keys(
+            &[(48, &single[..4000]), (48, &single), (51, &unison)],
+            sample_rate,
+        );
+
+        let a4 = report.keys[48].unwrap();
+        assert!((a4.tracked_hz.unwrap() - 440.3).abs() < 0.05, "{a4:?}");
+        assert_eq!(a4.beat_hz, None);
+        let c5 = report.keys[51].unwrap();
+        assert!((c5.beat_hz.unwrap() - 1.0).abs() < 0.2, "{c5:?}");
+        // a key without notes isn't tracked
+        assert_eq!(report.keys[0].unwrap().tracked_hz, None);
+    }
+}
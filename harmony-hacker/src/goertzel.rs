@@ -1,5 +1,20 @@
 //! Minimalistic implementation of the Goertzel algorithm.
 //! https://en.wikipedia.org/wiki/Goertzel_algorithm
+//!
+//! The DFT value is generalized to frequencies that aren't integer bins of the block:
+//! https://doi.org/10.1186/1687-6180-2012-56 (Sysel & Rajmic, "Goertzel algorithm generalized to
+//! non-integer multiples of fundamental frequency", 2012)
+
+use std::f32::consts::PI;
+
+use realfft::num_complex::Complex;
+
+/// The floor of [`Goertzel::power_db`], so silence isn't minus infinity
+const MIN_POWER_DB: f32 = -120.0;
+
+/// How much the oldest sample of the sliding Goertzel block fades, so rounding errors fade out
+/// instead of accumulating while the magnitude stays within 0.1% of the block Goertzel
+const SLIDING_DECAY: f64 = 1e-3;
 
 /// A helper function to calculate the magnitude of the signal at the target frequency in a single call.
 /// Example:
@@ -62,6 +77,8 @@ pub(crate) struct Goertzel {
     q1: f32,
     q2: f32,
     coeff: f32,
+    /// The target frequency in radians per sample
+    w: f32,
 }
 
 impl Goertzel {
@@ -76,6 +93,7 @@ impl Goertzel {
             q1: 0.0,
             q2: 0.0,
             coeff,
+            w,
         }
     }
 
@@ -87,7 +105,8 @@ impl Goertzel {
         self.q1 = self.q0;
     }
 
-    /// Get the magnitude of the signal sampled before
+    /// Get the magnitude of the signal sampled before. It's exact for any frequency, as the phase
+    /// correction of [`Goertzel::complex`] doesn't change it.
     pub(crate) fn magnitude(&self, block_size: u32) -> f32 {
         let magnitude =
             ((self.q1 * self.q1) + (self.q2 * self.q2) - (self.q1 * self.q2 * self.coeff)).sqrt();
@@ -95,6 +114,27 @@ impl Goertzel {
         2.0 * magnitude / block_size as f32
     }
 
+    /// The DFT value of the block at the target frequency, exact for non-integer bins too:
+    /// X = e^(-jwN) * (e^(jw) * s[N-1] - s[N-2])
+    /// The final phase correction e^(-jwN) is 1 only for integer bins.
+    pub(crate) fn complex(&self, block_size: u32) -> Complex<f32> {
+        // the angle grows with the block, so it's wrapped in f64 before losing precision
+        let correction = (-(self.w as f64) * block_size as f64) % std::f64::consts::TAU;
+        Complex::from_polar(1.0, correction as f32)
+            * (Complex::from_polar(1.0, self.w) * self.q1 - self.q2)
+    }
+
+    /// The phase of the DFT value in radians, zero for a cosine starting at the block start
+    pub(crate) fn phase(&self, block_size: u32) -> f32 {
+        self.complex(block_size).arg()
+    }
+
+    /// The power of the normalized magnitude in dB, 0 dB is a sine of amplitude 1
+    pub(crate) fn power_db(&self, block_size: u32) -> f32 {
+        let magnitude = self.magnitude(block_size);
+        (10.0 * (magnitude * magnitude).log10()).max(MIN_POWER_DB)
+    }
+
     /// Reset the filter's state
     pub(crate) fn reset(&mut self) {
         self.q0 = 0.0;
@@ -103,6 +143,106 @@ impl Goertzel {
     }
 }
 
+/// Phase vocoder estimation of the frequency near `target_frequency` from phases of two blocks
+/// `hop` samples apart. Beating unison strings show up as the estimate wobbling around the key.
+pub(crate) fn instantaneous_frequency(
+    previous_phase: f32,
+    phase: f32,
+    hop: usize,
+    sample_rate: u32,
+    target_frequency: f32,
+) -> f32 {
+    let expected = 2.0 * PI * target_frequency * hop as f32 / sample_rate as f32;
+    // the deviation from the expected advance, wrapped to -pi..pi
+    let deviation = (phase - previous_phase - expected + PI).rem_euclid(2.0 * PI) - PI;
+    target_frequency + deviation * sample_rate as f32 / (2.0 * PI * hop as f32)
+}
+
+/// A block of [`track`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct TrackedBlock {
+    /// The instantaneous frequency since the previous block, the target one for the first block
+    pub(crate) frequency_hz: f32,
+    pub(crate) power_db: f32,
+}
+
+/// Follows the signal near `target_frequency` through blocks of `block_size` samples taken every
+/// `hop` samples, with the power and the phase vocoder frequency of each block
+pub(crate) fn track(
+    samples: &[f32],
+    sample_rate: u32,
+    target_frequency: f32,
+    block_size: usize,
+    hop: usize,
+) -> Vec<TrackedBlock> {
+    let hop = hop.max(1);
+    let mut goertzel = Goertzel::new(sample_rate, target_frequency);
+    let mut previous_phase = None;
+    samples
+        .windows(block_size.max(1))
+        .step_by(hop)
+        .map(|block| {
+            goertzel.reset();
+            for sample in block {
+                goertzel.process(*sample);
+            }
+            let phase = goertzel.phase(block.len() as u32);
+            let frequency_hz = previous_phase.map_or(target_frequency, |previous_phase| {
+                instantaneous_frequency(previous_phase, phase, hop, sample_rate, target_frequency)
+            });
+            previous_phase = Some(phase);
+            TrackedBlock {
+                frequency_hz,
+                power_db: goertzel.power_db(block.len() as u32),
+            }
+        })
+        .collect()
+}
+
+/// Sliding Goertzel (sliding DFT) over the last `block_size` samples, updated per sample in O(1):
+/// S[n] = r * e^(jw) * S[n-1] + x[n] - r^N * e^(jwN) * x[n-N]
+/// https://www.dsprelated.com/showarticle/776.php (Lyons, "The Sliding DFT", 2015)
+///
+/// The rectangular window is the only one, and old samples fade by the damping factor `r`, so the
+/// filter stays stable without the exact pole on the unit circle. The damping is derived from the
+/// block size, so r^N is the same for any block, and it's too close to 1 for f32, so the state is
+/// kept in f64.
+pub(crate) struct SlidingGoertzel {
+    state: Complex<f64>,
+    /// r * e^(jw)
+    rotation: Complex<f64>,
+    /// r^N * e^(jwN)
+    dropped_rotation: Complex<f64>,
+    block_size: usize,
+}
+
+impl SlidingGoertzel {
+    pub(crate) fn new(sample_rate: u32, target_frequency: f32, block_size: usize) -> Self {
+        let w = 2.0 * std::f64::consts::PI * target_frequency as f64 / sample_rate as f64;
+        let damping = 1.0 - SLIDING_DECAY / block_size.max(1) as f64;
+        Self {
+            state: Complex::default(),
+            rotation: Complex::from_polar(damping, w),
+            dropped_rotation: Complex::from_polar(
+                damping.powi(block_size as i32),
+                w * block_size as f64 % std::f64::consts::TAU,
+            ),
+            block_size,
+        }
+    }
+
+    /// Slide the block by a sample, `dropped` is the sample `block_size` samples before
+    pub(crate) fn update(&mut self, sample: f32, dropped: f32) {
+        self.state =
+            self.rotation * self.state + sample as f64 - self.dropped_rotation * dropped as f64;
+    }
+
+    /// The magnitude of the signal in the last block, as [`Goertzel::magnitude`]
+    pub(crate) fn magnitude(&self) -> f32 {
+        (2.0 * self.state.norm() / self.block_size as f64) as f32
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -181,4 +321,147 @@ mod tests {
         let magnitude = e4_goertzel.magnitude(samples.len() as u32);
         assert!(0.99 < magnitude && magnitude < 1.01);
     }
+
+    #[test]
+    fn sliding_goertzel_test() {
+        // a short block and a second long block as at 1 Hz resolution
+        for (sample_rate, block_size) in [(8000, 400), (48000, 48000)] {
+            // two keys with a noise-like detuned tone
+            let len = 5 * block_size;
+            let samples: Vec<f32> = (0..len)
+                .map(|i| {
+                    let t = i as f32 / sample_rate as f32;
+                    let envelope = (i as f32 / len as f32 * 7.0).sin().abs();
+                    envelope * (2.0 * PI * 440.0 * t).sin()
+                        + 0.5 * (2.0 * PI * 446.3 * t + t * t).sin()
+                })
+                .collect();
+
+            for frequency in [440.0, 443.0, 261.6256] {
+                let mut sliding = SlidingGoertzel::new(sample_rate, frequency, block_size);
+                for (i, sample) in samples.iter().enumerate() {
+                    let dropped = i.checked_sub(block_size).map_or(0.0, |i| samples[i]);
+                    sliding.update(*sample, dropped);
+
+                    // a block ends at every 997th sample, including incomplete first blocks
+                    if i % 997 == 0 {
+                        let block = &samples[(i + 1).saturating_sub(block_size)..=i];
+                        let expected = goertzel(block, sample_rate, frequency) * block.len() as f32
+                            / block_size as f32;
+                        assert!(
+                            (sliding.magnitude() - expected).abs() < 2e-3 + 2e-3 * expected,
+                            "{block_size} {frequency} {i}: {} != {expected}",
+                            sliding.magnitude()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn complex_test() {
+        let sample_rate = 8000;
+        let block_size = 400;
+        // 440 Hz is the bin 22 of the block
+        for phase in [0.0, 1.0, -2.5] {
+            let mut goertzel = Goertzel::new(sample_rate, 440.0);
+            for i in 0..block_size {
+                let t = i as f32 / sample_rate as f32;
+                goertzel.process(0.5 * (2.0 * PI * 440.0 * t + phase).cos());
+            }
+            // half of the amplitude times the block size, the other half is at the negative bin
+            let complex = goertzel.complex(block_size);
+            assert!((complex.norm() - 100.0).abs() < 0.1, "{complex}");
+            assert!((goertzel.phase(block_size) - phase).abs() < 1e-3, "{phase}");
+            assert!((goertzel.power_db(block_size) + 6.0206).abs() < 1e-3);
+        }
+
+        let goertzel = Goertzel::new(sample_rate, 440.0);
+        assert_eq!(goertzel.power_db(block_size), MIN_POWER_DB);
+    }
+
+    #[test]
+    fn instantaneous_frequency_test() {
+        let sample_rate = 8000;
+        let (block_size, hop) = (800, 200);
+        let frequency = 441.7;
+        let samples: Vec<f32> = (0..block_size + hop)
+            .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect();
+
+        let phase = |start: usize| {
+            let mut goertzel = Goertzel::new(sample_rate, 440.0);
+            for sample in &samples[start..start + block_size] {
+                goertzel.process(*sample);
+            }
+            goertzel.phase(block_size as u32)
+        };
+        let estimate = instantaneous_frequency(phase(0), phase(hop), hop, sample_rate, 440.0);
+        assert!((estimate - frequency).abs() < 0.05, "{estimate}");
+    }
+
+    #[test]
+    fn track_test() {
+        let sample_rate = 8000;
+        let frequency = 441.7;
+        let samples: Vec<f32> = (0..8000)
+            .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect();
+
+        let blocks = track(&samples, sample_rate, 440.0, 800, 200);
+        assert_eq!(blocks.len(), 37);
+        assert_eq!(blocks[0].frequency_hz, 440.0);
+        for block in &blocks[1..] {
+            assert!((block.frequency_hz - frequency).abs() < 0.05, "{block:?}");
+            // a sine of amplitude 1 is 0 dB, slightly less off the target
+            assert!((-1.0..0.0).contains(&block.power_db), "{block:?}");
+        }
+        assert_eq!(track(&samples[..799], sample_rate, 440.0, 800, 200), vec![]);
+    }
+
+    #[test]
+    fn generalized_goertzel_test() {
+        let sample_rate = 8000;
+        let block_size = 400;
+        let samples: Vec<f32> = (0..block_size)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                (2.0 * PI * 443.3 * t + 0.7).sin() + 0.3 * (2.0 * PI * 1234.5 * t).cos()
+            })
+            .collect();
+
+        // frequencies sweep from the bin 22 to the bin 23 and between bins of the second tone
+        let frequencies = (0..=10)
+            .map(|step| 440.0 + 2.0 * step as f32)
+            .chain([1230.0, 1234.5, 1239.0]);
+        for frequency in frequencies {
+            let mut goertzel = Goertzel::new(sample_rate, frequency);
+            for sample in &samples {
+                goertzel.process(*sample);
+            }
+
+            // the DFT by definition, in f64
+            let w = 2.0 * std::f64::consts::PI * frequency as f64 / sample_rate as f64;
+            let (re, im) = samples
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (n, x)| {
+                    let angle = w * n as f64;
+                    (re + *x as f64 * angle.cos(), im - *x as f64 * angle.sin())
+                });
+            let expected = Complex::new(re as f32, im as f32);
+
+            let complex = goertzel.complex(block_size);
+            assert!(
+                (complex - expected).norm() < 0.05,
+                "{frequency}: {complex} != {expected}"
+            );
+            let magnitude = goertzel.magnitude(block_size);
+            assert!(
+                (magnitude - 2.0 * expected.norm() / block_size as f32).abs() < 1e-4,
+                "{frequency}: {magnitude}"
+            );
+        }
+    }
 }
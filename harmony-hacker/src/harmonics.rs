@@ -0,0 +1,288 @@
+//! Overtone-aware key activation: combines magnitudes of each key's partials, so overtones of a
+//! low note don't light up the keys they fall on.
+//!
+//! Partials of a key fall near higher keys: the 2nd on the octave, the 3rd on the twelfth and so
+//! on, but stretched by the inharmonicity and, from the 7th on, far from any key. So magnitudes are
+//! measured at each partial of each key, and each key gets a harmonic template with the expected
+//! amplitudes of its partials.
+//! https://www.music.mcgill.ca/~ich/classes/mumt611_07/MIREX/Klapuri.pdf
+
+use crate::{inharmonicity::Inharmonicity, notes::KEYS_COUNT};
+
+/// The number of partials in the template of a key, including the fundamental
+const PARTIALS: u32 = 8;
+/// Partials of different keys this close share the energy in the sparse decomposition, the
+/// overlap falls linearly to zero at this distance
+const OVERLAP_CENTS: f32 = 100.0;
+/// Iterations of the sparse decomposition
+const SPARSE_ITERATIONS: usize = 50;
+/// The weight of the L1 penalty of the sparse decomposition, the higher the fewer keys stay active
+const SPARSITY: f32 = 0.01;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum KeyActivation {
+    /// Raw magnitude at the key's fundamental
+    #[default]
+    Fundamental,
+    /// Magnitudes of partials projected on the template
+    HarmonicSum,
+    /// Geometric mean of magnitudes of partials relative to the template, a key is active only if
+    /// all its partials are
+    HarmonicProduct,
+    /// Non-negative sparse decomposition of all magnitudes into templates of keys
+    Sparse,
+}
+
+/// Partials of keys magnitudes are measured at, and expected amplitudes of partials of each key
+pub(crate) struct HarmonicTemplates {
+    /// Frequencies of partials, the first `KEYS_COUNT` are fundamentals of keys
+    frequencies: Vec<f32>,
+    /// Partials of each key as pairs of the index in `frequencies` and the amplitude relative to
+    /// the fundamental
+    templates: Vec<Vec<(usize, f32)>>,
+    /// Templates spread over partials of all keys close to partials of the key, so keys sharing
+    /// a partial compete for its energy
+    overlapping: Vec<Vec<(usize, f32)>>,
+}
+
+impl HarmonicTemplates {
+    /// Templates of keys with fundamentals at `key_frequency`. Partials above `max_hz` are dropped.
+    pub(crate) fn new(
+        key_frequency: impl Fn(u8) -> f32,
+        inharmonicity: &Inharmonicity,
+        max_hz: f32,
+    ) -> Self {
+        let mut frequencies: Vec<f32> = (0..KEYS_COUNT as u8).map(&key_frequency).collect();
+        let templates: Vec<Vec<(usize, f32)>> = (0..KEYS_COUNT as u8)
+            .map(|key| {
+                // amplitudes of partials of a struck string roughly fall as 1/n
+                let mut template = vec![(key as usize, 1.0)];
+                for n in 2..=PARTIALS {
+                    let partial_hz = inharmonicity.partial(key, key_frequency(key), n);
+                    if partial_hz <= max_hz {
+                        template.push((frequencies.len(), 1.0 / n as f32));
+                        frequencies.push(partial_hz);
+                    }
+                }
+                template
+            })
+            .collect();
+
+        let overlapping = templates
+            .iter()
+            .map(|template| {
+                template
+                    .iter()
+                    .flat_map(|&(partial, amplitude)| {
+                        let partial_hz = frequencies[partial];
+                        frequencies
+                            .iter()
+                            .enumerate()
+                            .filter_map(move |(other, hz)| {
+                                let cents = 1200.0 * (hz / partial_hz).log2().abs();
+                                (cents < OVERLAP_CENTS)
+                                    .then(|| (other, amplitude * (1.0 - cents / OVERLAP_CENTS)))
+                            })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            frequencies,
+            templates,
+            overlapping,
+        }
+    }
+
+    /// Frequencies magnitudes of frames are measured at
+    pub(crate) fn frequencies(&self) -> &[f32] {
+        &self.frequencies
+    }
+
+    /// Activations of keys in a frame of magnitudes at `frequencies`. Only fundamentals are needed
+    /// for [`KeyActivation::Fundamental`].
+    pub(crate) fn activations(
+        &self,
+        magnitudes: &[f32],
+        method: KeyActivation,
+    ) -> [f32; KEYS_COUNT] {
+        match method {
+            KeyActivation::Fundamental => std::array::from_fn(|key| magnitudes[key]),
+            KeyActivation::HarmonicSum => std::array::from_fn(|key| {
+                // the least squares amplitude of the template, which is the fundamental magnitude
+                // if partials match the template exactly
+                let template = &self.templates[key];
+                let projection: f32 = template
+                    .iter()
+                    .map(|(partial, amplitude)| magnitudes[*partial] * amplitude)
+                    .sum();
+                let norm: f32 = template
+                    .iter()
+                    .map(|(_, amplitude)| amplitude * amplitude)
+                    .sum();
+                projection / norm
+            }),
+            KeyActivation::HarmonicProduct => std::array::from_fn(|key| {
+                let template = &self.templates[key];
+                let log_sum: f32 = template
+                    .iter()
+                    .map(|(partial, amplitude)| {
+                        (magnitudes[*partial] / amplitude)
+                            .max(f32::MIN_POSITIVE)
+                            .ln()
+                    })
+                    .sum();
+                (log_sum / template.len() as f32).exp()
+            }),
+            KeyActivation::Sparse => self.sparse(magnitudes),
+        }
+    }
+
+    /// Non-negative activations `a` minimizing `|x - W a|^2 / 2 + SPARSITY * |a|` with multiplicative
+    /// updates, where `x` are magnitudes and columns of `W` are overlapping templates.
+    /// https://arxiv.org/abs/cs/0202009 (Hoyer, "Non-negative sparse coding")
+    fn sparse(&self, magnitudes: &[f32]) -> [f32; KEYS_COUNT] {
+        // W^T x doesn't change between iterations
+        let numerators: [f32; KEYS_COUNT] = std::array::from_fn(|key| {
+            self.overlapping[key]
+                .iter()
+                .map(|(partial, amplitude)| magnitudes[*partial] * amplitude)
+                .sum()
+        });
+
+        let mut activations: [f32; KEYS_COUNT] = std::array::from_fn(|key| magnitudes[key]);
+        let mut reconstruction = vec![0.0; self.frequencies.len()];
+        for _ in 0..SPARSE_ITERATIONS {
+            reconstruction.fill(0.0);
+            for (template, activation) in self.overlapping.iter().zip(&activations) {
+                for (partial, amplitude) in template {
+                    reconstruction[*partial] += amplitude * activation;
+                }
+            }
+            for (key, activation) in activations.iter_mut().enumerate() {
+                let denominator: f32 = self.overlapping[key]
+                    .iter()
+                    .map(|(partial, amplitude)| reconstruction[*partial] * amplitude)
+                    .sum();
+                *activation *= numerators[key] / (denominator + SPARSITY + f32::EPSILON);
+            }
+        }
+        activations
+    }
+}
+
+/// Activations of keys in each frame of magnitudes at frequencies of `templates`
+pub(crate) fn key_activations(
+    frames: &[Vec<f32>],
+    templates: &HarmonicTemplates,
+    method: KeyActivation,
+) -> Vec<[f32; KEYS_COUNT]> {
+    frames
+        .iter()
+        .map(|frame| templates.activations(frame, method))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn key_frequency(key: u8) -> f32 {
+        27.5 * 2.0f32.powf(key as f32 / 12.0)
+    }
+
+    fn templates() -> HarmonicTemplates {
+        HarmonicTemplates::new(key_frequency, &Inharmonicity::default(), 4200.0)
+    }
+
+    /// Magnitudes at partials of keys with given notes sounding, each one with partials falling as
+    /// 1/n. Partials within 20 cents are measured by the same filter.
+    fn frame(templates: &HarmonicTemplates, notes: &[u8]) -> Vec<f32> {
+        let inharmonicity = &Inharmonicity::default();
+        templates
+            .frequencies()
+            .iter()
+            .map(|hz| {
+                let sounding: f32 = notes
+                    .iter()
+                    .flat_map(|&key| {
+                        (1..=PARTIALS)
+                            .map(move |n| (n, inharmonicity.partial(key, key_frequency(key), n)))
+                    })
+                    .filter(|(_, partial_hz)| 1200.0 * (hz / partial_hz).log2().abs() < 20.0)
+                    .map(|(n, _)| 0.5 / n as f32)
+                    .sum();
+                0.001 + sounding
+            })
+            .collect()
+    }
+
+    #[test]
+    fn templates_test() {
+        let templates = templates();
+        let inharmonicity = Inharmonicity::default();
+        let partials = |key: usize| -> Vec<f32> {
+            templates.templates[key]
+                .iter()
+                .map(|(partial, _)| templates.frequencies()[*partial])
+                .collect()
+        };
+
+        // the fundamental is the key, partials are stretched
+        assert_eq!(partials(0).len(), 8);
+        assert_eq!(partials(0)[0], key_frequency(0));
+        for (n, hz) in partials(24).iter().enumerate() {
+            assert_eq!(
+                *hz,
+                inharmonicity.partial(24, key_frequency(24), n as u32 + 1)
+            );
+        }
+        // the 7th harmonic is 31 cents flat of the nearest key, and the 7th partial of A2 is still
+        // more than 20 cents flat with the inharmonicity, so it needs own filter
+        let cents = 1200.0 * (partials(24)[6] / key_frequency(24 + 34)).log2();
+        assert!((-31.0..-20.0).contains(&cents), "{cents}");
+
+        // partials above the limit are dropped
+        assert_eq!(partials(60).len(), 4);
+        assert_eq!(partials(87).len(), 1);
+        assert_eq!(templates.templates[0][1].1, 0.5);
+    }
+
+    #[test]
+    fn activations_test() {
+        let templates = templates();
+
+        // A2 alone: the octave and the twelfth are strong in raw magnitudes, but not in activations
+        let magnitudes = frame(&templates, &[24]);
+        let fundamental = templates.activations(&magnitudes, KeyActivation::Fundamental);
+        assert!(fundamental[36] > 0.2 && fundamental[43] > 0.15);
+        for method in [
+            KeyActivation::HarmonicSum,
+            KeyActivation::HarmonicProduct,
+            KeyActivation::Sparse,
+        ] {
+            let activations = templates.activations(&magnitudes, method);
+            assert!(activations[24] > 0.4, "{method:?} {}", activations[24]);
+            for ghost in [36, 43, 48] {
+                assert!(
+                    activations[ghost] < activations[24] / 2.0,
+                    "{method:?} {ghost} {}",
+                    activations[ghost]
+                );
+            }
+        }
+
+        // C major chord in the 4th octave, the decomposition leaves only played keys
+        let chord = [39, 43, 46];
+        let activations = templates.activations(&frame(&templates, &chord), KeyActivation::Sparse);
+        for (key, activation) in activations.iter().enumerate() {
+            if chord.contains(&(key as u8)) {
+                assert!(*activation > 0.4, "{key} {activation}");
+            } else {
+                assert!(*activation < 0.1, "{key} {activation}");
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Harmonic-percussive source separation by median filtering of the spectrogram.
+//! https://www.researchgate.net/publication/254583990 (Fitzgerald, "Harmonic/percussive separation
+//! using median filtering", 2010)
+//!
+//! Harmonic sounds are horizontal lines in the spectrogram, so the median along time keeps them and
+//! removes hammer strikes and other clicks, which are vertical lines kept by the median along
+//! frequency. Both medians form a soft mask applied to the spectrogram before the inverse STFT.
+
+use crate::stft;
+
+/// The length of the analysis window, rounded up to a power of two
+const WINDOW_SEC: f32 = 0.09;
+/// Frames per window, the hop is a quarter of the window
+const OVERLAP_FACTOR: usize = 4;
+/// The length of the median filter along time in frames, about 0.35 sec with the default window
+const HARMONIC_KERNEL: usize = 17;
+/// The length of the median filter along frequency in bins, about 180 Hz at 48 kHz
+const PERCUSSIVE_KERNEL: usize = 17;
+
+/// The harmonic component of samples with the same length
+pub(crate) fn harmonic(samples: &[f32], sample_rate: u32) -> Vec<f32> {
+    let window_size = ((sample_rate as f32 * WINDOW_SEC) as usize).next_power_of_two();
+    let hop = window_size / OVERLAP_FACTOR;
+
+    stft::filter(samples, window_size, hop, |spectra| {
+        let magnitudes: Vec<Vec<f32>> = spectra
+            .iter()
+            .map(|spectrum| spectrum.iter().map(|bin| bin.norm()).collect())
+            .collect();
+
+        let percussive: Vec<Vec<f32>> = magnitudes
+            .iter()
+            .map(|frame| median_filter(frame, PERCUSSIVE_KERNEL))
+            .collect();
+        let bins = window_size / 2 + 1;
+        let mut harmonic = vec![vec![0.0; bins]; magnitudes.len()];
+        let mut track = vec![0.0; magnitudes.len()];
+        for bin in 0..bins {
+            for (value, frame) in track.iter_mut().zip(&magnitudes) {
+                *value = frame[bin];
+            }
+            for (frame, value) in harmonic
+                .iter_mut()
+                .zip(median_filter(&track, HARMONIC_KERNEL))
+            {
+                frame[bin] = value;
+            }
+        }
+
+        for ((spectrum, harmonic), percussive) in spectra.iter_mut().zip(&harmonic).zip(&percussive)
+        {
+            for ((bin, h), p) in spectrum.iter_mut().zip(harmonic).zip(percussive) {
+                // Wiener-like soft mask, so that the harmonic and percussive components sum up
+                // to the original signal
+                *bin *= h * h / (h * h + p * p + f32::MIN_POSITIVE);
+            }
+        }
+    })
+}
+
+/// Medians of `kernel` values around each value, the window shrinks at the edges
+fn median_filter(values: &[f32], kernel: usize) -> Vec<f32> {
+    let half = kernel / 2;
+    let mut window = Vec::with_capacity(kernel);
+    (0..values.len())
+        .map(|i| {
+            window.clear();
+            window
+                .extend_from_slice(&values[i.saturating_sub(half)..values.len().min(i + half + 1)]);
+            let middle = window.len() / 2;
+            *window.select_nth_unstable_by(middle, f32::total_cmp).1
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn median_filter_test() {
+        assert_eq!(median_filter(&[], 3), vec![]);
+        assert_eq!(
+            median_filter(&[1.0, 5.0, 2.0, 8.0, 3.0], 3),
+            vec![5.0, 2.0, 5.0, 3.0, 8.0]
+        );
+        // a single spike is removed
+        assert_eq!(
+            median_filter(&[1.0, 1.0, 9.0, 1.0, 1.0], 3),
+            vec![1.0, 1.0, 1.0, 1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn harmonic_test() {
+        let sample_rate = 8000;
+        let tone: Vec<f32> = (0..sample_rate * 2)
+            .map(|i| {
+                0.5 * (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin()
+            })
+            .collect();
+        let mut clicks = vec![0.0; tone.len()];
+        for i in (1000..clicks.len()).step_by(4000) {
+            clicks[i] = 1.0;
+        }
+        let mixed: Vec<f32> = tone.iter().zip(&clicks).map(|(t, c)| t + c).collect();
+
+        let harmonic = harmonic(&mixed, sample_rate as u32);
+        assert_eq!(harmonic.len(), mixed.len());
+
+        // the tone is kept and clicks are gone, except at the edges where the median filter sees
+        // the silence of the padding
+        let range = 2000..harmonic.len() - 2000;
+        let error = harmonic[range.clone()]
+            .iter()
+            .zip(&tone[range])
+            .map(|(h, t)| (h - t).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.15, "{error}");
+    }
+}
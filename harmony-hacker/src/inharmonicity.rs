@@ -0,0 +1,383 @@
+//! Inharmonicity of piano strings and the stretch tuning it leads to.
+//! https://en.wikipedia.org/wiki/Inharmonicity
+//! https://en.wikipedia.org/wiki/Piano_acoustics#The_Railsback_curve
+//!
+//! Stiff strings vibrate with partials above the harmonic series: the partial `n` of a string with
+//! the fundamental `f0` and the inharmonicity coefficient `B` sounds at `n * f0 * sqrt(1 + B * n^2)`.
+//! Tuners match partials of octaves instead of fundamentals, so octaves get wider than 2:1 and the
+//! treble ends up sharp while the bass ends up flat (the Railsback curve).
+
+use realfft::RealFftPlanner;
+
+use crate::{notes::KEYS_COUNT, window_fn};
+
+/// Registers keys are grouped in, each one is an octave starting from A
+pub(crate) const REGISTERS_COUNT: usize = 8;
+/// The longest part of a note used for the estimation, longer ones don't improve the precision much
+const MAX_ESTIMATION_SEC: f32 = 1.0;
+/// The number of partials looked for in a note
+const MAX_PARTIALS: u32 = 16;
+/// Partials weaker than the strongest peak by this ratio (-60 dB) are ignored
+const MIN_PARTIAL_RATIO: f32 = 0.001;
+/// The half-width of the search range of the first partial around the expected one, in cents
+const FIRST_PARTIAL_RANGE_CENTS: f32 = 50.0;
+
+/// The frequency of the partial `n` (1 is the fundamental) of the string with the coefficient `b`
+pub(crate) fn partial_frequency(f0: f32, n: u32, b: f32) -> f32 {
+    let n = n as f32;
+    n * f0 * (1.0 + b * n * n).sqrt()
+}
+
+/// The register 0..REGISTERS_COUNT of the key number 0..88
+pub(crate) fn register(key: u8) -> usize {
+    (key as usize / 12).min(REGISTERS_COUNT - 1)
+}
+
+/// Typical inharmonicity of a grand piano: the sum of two exponentials, one falling over the wound
+/// bass strings and one growing over the plain treble strings.
+/// Rigaud et al. "A parametric model and estimation techniques for the inharmonicity and tuning
+/// of the piano", JASA 133 (2013).
+pub(crate) fn model_coefficient(key: u8) -> f32 {
+    let key = key as f32;
+    (-0.096 * key - 7.6).exp() + (0.0875 * key - 11.52).exp()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Stretch {
+    /// Keys are tuned exactly to the temperament
+    #[default]
+    None,
+    /// Octaves are tuned by matching the 4th partial of the lower key to the 2nd partial of the upper one
+    Railsback,
+}
+
+/// Inharmonicity coefficients of all keys of the piano
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Inharmonicity {
+    coefficients: [f32; KEYS_COUNT],
+}
+
+impl Default for Inharmonicity {
+    fn default() -> Self {
+        Self {
+            coefficients: std::array::from_fn(|key| model_coefficient(key as u8)),
+        }
+    }
+}
+
+impl Inharmonicity {
+    /// Scales the model to match coefficients estimated per register. The scale is interpolated
+    /// between registers in the log domain, registers without estimates keep the model.
+    pub(crate) fn from_registers(registers: &[Option<f32>; REGISTERS_COUNT]) -> Self {
+        // Log ratios to the model at the centers of registers with estimates
+        let center = |register: usize| (register * 12 + 6).min(KEYS_COUNT - 1) as u8;
+        let ratios: Vec<(f32, f32)> = registers
+            .iter()
+            .enumerate()
+            .filter_map(|(register, coefficient)| {
+                let key = center(register);
+                coefficient
+                    .filter(|coefficient| *coefficient > 0.0)
+                    .map(|coefficient| (key as f32, (coefficient / model_coefficient(key)).ln()))
+            })
+            .collect();
+        if ratios.is_empty() {
+            return Self::default();
+        }
+
+        let log_ratio = |key: f32| {
+            let next = ratios.partition_point(|(center, _)| *center < key);
+            match (
+                next.checked_sub(1).map(|prev| ratios[prev]),
+                ratios.get(next).copied(),
+            ) {
+                (Some((from, a)), Some((to, b))) => a + (b - a) * (key - from) / (to - from),
+                (Some((_, ratio)), None) | (None, Some((_, ratio))) => ratio,
+                (None, None) => 0.0,
+            }
+        };
+        Self {
+            coefficients: std::array::from_fn(|key| {
+                model_coefficient(key as u8) * log_ratio(key as f32).exp()
+            }),
+        }
+    }
+
+    /// The inharmonicity coefficient of the key number 0..88
+    pub(crate) fn coefficient(&self, key: u8) -> f32 {
+        self.coefficients[key as usize]
+    }
+
+    /// The frequency of the partial `n` of the key with the given first partial frequency
+    pub(crate) fn partial(&self, key: u8, first_partial_hz: f32, n: u32) -> f32 {
+        let b = self.coefficient(key);
+        partial_frequency(first_partial_hz / (1.0 + b).sqrt(), n, b)
+    }
+
+    /// Deviations of keys from the temperament in cents with the Railsback stretch. The octave
+    /// A4 - A5 is split evenly, other octaves are tuned outwards from it.
+    pub(crate) fn stretch_cents(&self) -> [f32; KEYS_COUNT] {
+        // The ratio of the partial n to the first partial
+        let ratio = |key: usize, n: u32| self.partial(key as u8, 1.0, n);
+        let octave_cents =
+            |lower: usize| 1200.0 * (ratio(lower, 4) / ratio(lower + 12, 2)).log2() - 1200.0;
+
+        let mut stretch = [0.0; KEYS_COUNT];
+        let a4_octave = octave_cents(48);
+        for (i, key) in (48..60).enumerate() {
+            stretch[key] = a4_octave * i as f32 / 12.0;
+        }
+        for key in 60..KEYS_COUNT {
+            stretch[key] = stretch[key - 12] + octave_cents(key - 12);
+        }
+        for key in (0..48).rev() {
+            stretch[key] = stretch[key + 12] - octave_cents(key);
+        }
+        stretch
+    }
+}
+
+/// The first partial and the inharmonicity of a single note measured from its partials
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct PartialsEstimate {
+    pub(crate) first_partial_hz: f32,
+    pub(crate) coefficient: f32,
+    /// The number of partials found
+    pub(crate) partials: u32,
+}
+
+/// The shortest note `estimate_partials` measures, a period of the expected first partial
+pub(crate) fn min_estimation_samples(sample_rate: u32, expected_hz: f32) -> usize {
+    (sample_rate as f32 / expected_hz).ceil().max(1.0) as usize
+}
+
+/// Measures partials of a single note expected to sound at `expected_hz`.
+/// Returns `None` if the note is shorter than [`min_estimation_samples`], or if the first partial
+/// or at least 2 of the upper ones are not found.
+pub(crate) fn estimate_partials(
+    samples: &[f32],
+    sample_rate: u32,
+    expected_hz: f32,
+) -> Option<PartialsEstimate> {
+    if samples.len() < min_estimation_samples(sample_rate, expected_hz) {
+        return None;
+    }
+    let samples = &samples[..samples
+        .len()
+        .min((MAX_ESTIMATION_SEC * sample_rate as f32) as usize)];
+    // Zero-padding to 4 times the length interpolates the spectrum for more precise peaks
+    let fft_size = (samples.len() * 4).next_power_of_two();
+    let bin_hz = sample_rate as f32 / fft_size as f32;
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let r2c = planner.plan_fft_forward(fft_size);
+    let mut input = r2c.make_input_vec();
+    let mut spectrum = r2c.make_output_vec();
+    for ((input, sample), window) in input
+        .iter_mut()
+        .zip(samples)
+        .zip(window_fn::hann(samples.len()))
+    {
+        *input = sample * window;
+    }
+    r2c.process(&mut input, &mut spectrum).ok()?;
+    let magnitudes: Vec<f32> = spectrum.iter().map(|bin| bin.norm()).collect();
+    let min_magnitude = magnitudes.iter().copied().fold(0.0, f32::max) * MIN_PARTIAL_RATIO;
+
+    // The strongest local maximum in the range with the parabolic interpolation of the log magnitude
+    let peak = |from_hz: f32, to_hz: f32| -> Option<f32> {
+        let from = ((from_hz / bin_hz).floor() as usize).max(1);
+        let to = ((to_hz / bin_hz).ceil() as usize).min(magnitudes.len() - 2);
+        let k = (from..=to)
+            .filter(|&k| magnitudes[k] > magnitudes[k - 1] && magnitudes[k] >= magnitudes[k + 1])
+            .filter(|&k| magnitudes[k] > min_magnitude)
+            .max_by(|a, b| magnitudes[*a].total_cmp(&magnitudes[*b]))?;
+        let (prev, peak, next) = (
+            magnitudes[k - 1].ln(),
+            magnitudes[k].ln(),
+            magnitudes[k + 1].max(1e-10).ln(),
+        );
+        let denominator = prev - 2.0 * peak + next;
+        let shift = if denominator < 0.0 {
+            0.5 * (prev - next) / denominator
+        } else {
+            0.0
+        };
+        Some((k as f32 + shift) * bin_hz)
+    };
+
+    let range = 2.0f32.powf(FIRST_PARTIAL_RANGE_CENTS / 1200.0);
+    let first = peak(expected_hz / range, expected_hz * range)?;
+
+    // Each next partial is looked for around the position predicted by the partials found so far
+    let mut found = vec![(1, first)];
+    let (mut f0, mut b) = (first, 0.0);
+    for n in 2..=MAX_PARTIALS {
+        let predicted = partial_frequency(f0, n, b);
+        if predicted + first / 4.0 >= sample_rate as f32 / 2.0 {
+            break;
+        }
+        if let Some(frequency) = peak(predicted - first / 4.0, predicted + first / 4.0) {
+            found.push((n, frequency));
+            (f0, b) = fit(&found);
+        }
+    }
+
+    if found.len() < 3 {
+        return None;
+    }
+    Some(PartialsEstimate {
+        first_partial_hz: first,
+        coefficient: b,
+        partials: found.len() as u32,
+    })
+}
+
+/// Fits `f0` and `B` to partials by the linear regression of `(f_n / n)^2 = f0^2 + f0^2 * B * n^2`
+fn fit(partials: &[(u32, f32)]) -> (f32, f32) {
+    let points: Vec<(f32, f32)> = partials
+        .iter()
+        .map(|&(n, frequency)| ((n * n) as f32, (frequency / n as f32).powi(2)))
+        .collect();
+    let count = points.len() as f32;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / count;
+    let covariance: f32 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f32 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+
+    let slope = covariance / variance;
+    let intercept = mean_y - slope * mean_x;
+    (intercept.sqrt(), (slope / intercept).max(0.0))
+}
+
+/// The median of values measured for individual keys in each register, e.g. coefficients
+pub(crate) fn register_medians(key_values: &[(u8, f32)]) -> [Option<f32>; REGISTERS_COUNT] {
+    std::array::from_fn(|register_idx| {
+        let mut values: Vec<f32> = key_values
+            .iter()
+            .filter(|(key, _)| register(*key) == register_idx)
+            .map(|(_, value)| *value)
+            .collect();
+        values.sort_by(f32::total_cmp);
+        values.get(values.len() / 2).copied()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn model_test() {
+        assert_eq!(partial_frequency(100.0, 1, 0.0), 100.0);
+        assert_eq!(partial_frequency(100.0, 3, 0.0), 300.0);
+        assert!((partial_frequency(100.0, 10, 1e-3) - 1048.809).abs() < 1e-2);
+
+        // The minimum is in the low tenor, the treble is much more inharmonic than the bass
+        let model = Inharmonicity::default();
+        let min = (0..88).min_by(|a, b| model.coefficient(*a).total_cmp(&model.coefficient(*b)));
+        assert!((20..32).contains(&min.unwrap()), "{min:?}");
+        assert!(model.coefficient(0) > model.coefficient(24));
+        assert!(model.coefficient(87) > 10.0 * model.coefficient(0));
+
+        // The first partial stays in place
+        assert_eq!(model.partial(48, 440.0, 1), 440.0);
+        assert!(model.partial(48, 440.0, 2) > 880.0);
+
+        // Stretch grows towards both ends of the keyboard
+        let stretch = model.stretch_cents();
+        assert_eq!(stretch[48], 0.0);
+        for key in [12, 24, 36, 48, 60, 72, 84] {
+            assert!(stretch[key] > stretch[key - 12], "{key}");
+        }
+        assert!(stretch[87] > 30.0 && stretch[87] < 70.0, "{}", stretch[87]);
+        assert!(stretch[0] < -5.0 && stretch[0] > -30.0, "{}", stretch[0]);
+    }
+
+    #[test]
+    fn from_registers_test() {
+        assert_eq!(
+            Inharmonicity::from_registers(&[None; REGISTERS_COUNT]),
+            Inharmonicity::default()
+        );
+
+        // Twice the model everywhere
+        let doubled = Inharmonicity::from_registers(&std::array::from_fn(|register| {
+            Some(2.0 * model_coefficient((register as u8 * 12 + 6).min(87)))
+        }));
+        for key in 0..88 {
+            let ratio = doubled.coefficient(key) / model_coefficient(key);
+            assert!((ratio - 2.0).abs() < 1e-3, "{key}");
+        }
+
+        // A single estimate scales the whole keyboard
+        let mut registers = [None; REGISTERS_COUNT];
+        registers[4] = Some(model_coefficient(54) / 2.0);
+        let halved = Inharmonicity::from_registers(&registers);
+        assert!((halved.coefficient(0) / model_coefficient(0) - 0.5).abs() < 1e-3);
+
+        assert_eq!(
+            register_medians(&[(0, 1.0), (5, 3.0), (11, 2.0), (87, 4.0)]),
+            [Some(2.0), None, None, None, None, None, None, Some(4.0)]
+        );
+    }
+
+    #[test]
+    fn estimate_partials_test() {
+        let sample_rate = 16000;
+        let note = |f0: f32, b: f32| -> Vec<f32> {
+            (0..sample_rate)
+                .map(|i| {
+                    let t = i as f32 / sample_rate as f32;
+                    (1..=12)
+                        .map(|n| partial_frequency(f0, n, b))
+                        .filter(|frequency| *frequency < sample_rate as f32 / 2.0)
+                        .enumerate()
+                        .map(|(i, f)| (2.0 * std::f32::consts::PI * f * t).sin() / (i + 1) as f32)
+                        .sum()
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            estimate_partials(&vec![0.0; 16000], sample_rate, 440.0),
+            None
+        );
+
+        for (f0, b) in [(110.0, 4e-4), (220.0, 0.0), (440.0, 1e-3), (55.0, 2e-4)] {
+            // The expected frequency is slightly off, as for a detuned key
+            let estimate = estimate_partials(&note(f0, b), sample_rate, f0 * 1.01).unwrap();
+            let first = partial_frequency(f0, 1, b);
+            assert!(
+                (estimate.first_partial_hz - first).abs() < 0.05,
+                "{estimate:?} vs {first}"
+            );
+            assert!(
+                (estimate.coefficient - b).abs() < 5e-5,
+                "{estimate:?} vs {b}"
+            );
+            assert!(estimate.partials >= 10, "{estimate:?}");
+        }
+    }
+
+    #[test]
+    fn estimate_partials_short_test() {
+        let sample_rate = 16000;
+        let sine = |len: usize| -> Vec<f32> {
+            (0..len)
+                .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / 16000.0).sin())
+                .collect()
+        };
+        assert_eq!(min_estimation_samples(sample_rate, 440.0), 37);
+        assert_eq!(estimate_partials(&[], sample_rate, 440.0), None);
+        assert_eq!(estimate_partials(&sine(1), sample_rate, 440.0), None);
+        assert_eq!(estimate_partials(&sine(36), sample_rate, 440.0), None);
+        // a period or a sample at the highest frequency are measured without panics
+        estimate_partials(&sine(37), sample_rate, 440.0);
+        estimate_partials(&sine(1), sample_rate, sample_rate as f32);
+    }
+}
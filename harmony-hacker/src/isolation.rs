@@ -0,0 +1,436 @@
+//! Isolation of a band of the recording, e.g. to listen to the left hand bass line alone.
+//!
+//! Bins of the spectrogram outside the selected keys (with their partials) or frequency range are
+//! faded out before the inverse STFT. Keys are selected in the panel or by dragging over the
+//! spectrum with Shift held.
+
+use bevy::{prelude::*, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{
+    chroma, inharmonicity::Inharmonicity, notes::KEYS_COUNT, stft, wav, FftConfig, FftSource,
+    Keyboard, MainView, Spectrum, UpdateSpectrum,
+};
+
+/// The length of the analysis window, rounded up to a power of two. Long enough to resolve
+/// adjacent bass keys.
+const WINDOW_SEC: f32 = 0.15;
+/// Frames per window, the hop is a quarter of the window
+const OVERLAP_FACTOR: usize = 4;
+/// The number of partials of each selected key that are kept, including the fundamental
+const PARTIALS: u32 = 8;
+/// The half width of the band kept around each partial
+const BAND_CENTS: f32 = 50.0;
+/// Bins up to this distance from a band are kept, bass bands are narrower than a bin
+const KEPT_BINS: f32 = 1.0;
+/// Bins fade out up to this distance from a band, the half width of the Hann main lobe
+const FADED_BINS: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Band {
+    #[default]
+    Keys,
+    Frequencies,
+}
+
+#[derive(Clone, Debug, PartialEq, Resource)]
+pub(crate) struct Isolation {
+    /// Whether the selection is shown over the spectrum
+    pub(crate) enabled: bool,
+    pub(crate) band: Band,
+    pub(crate) first_key: u8,
+    pub(crate) last_key: u8,
+    /// Whether partials of selected keys are kept as well, otherwise only their fundamentals
+    pub(crate) with_partials: bool,
+    pub(crate) low_hz: f32,
+    pub(crate) high_hz: f32,
+    /// The key the selection is being dragged from
+    drag_start: Option<u8>,
+}
+
+impl Default for Isolation {
+    /// The bass register, A0 to B2
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            band: Band::Keys,
+            first_key: 0,
+            last_key: 26,
+            with_partials: true,
+            low_hz: 20.0,
+            high_hz: 250.0,
+            drag_start: None,
+        }
+    }
+}
+
+impl Isolation {
+    /// Frequency ranges kept by the selection
+    pub(crate) fn ranges(
+        &self,
+        key_frequency: impl Fn(u8) -> f32,
+        inharmonicity: &Inharmonicity,
+    ) -> Vec<(f32, f32)> {
+        match self.band {
+            Band::Keys => {
+                let partials = if self.with_partials { PARTIALS } else { 1 };
+                let band = 2.0f32.powf(BAND_CENTS / 1200.0);
+                let keys = self.first_key.min(self.last_key)..=self.first_key.max(self.last_key);
+                keys.flat_map(|key| {
+                    let first_partial_hz = key_frequency(key);
+                    (1..=partials).map(move |n| {
+                        let partial_hz = inharmonicity.partial(key, first_partial_hz, n);
+                        (partial_hz / band, partial_hz * band)
+                    })
+                })
+                .collect()
+            }
+            Band::Frequencies => {
+                vec![(self.low_hz.min(self.high_hz), self.low_hz.max(self.high_hz))]
+            }
+        }
+    }
+
+    /// A short description of the selection
+    fn label(&self) -> String {
+        match self.band {
+            Band::Keys => format!(
+                "{}-{}",
+                chroma::key_name(self.first_key.min(self.last_key)),
+                chroma::key_name(self.first_key.max(self.last_key))
+            ),
+            Band::Frequencies => format!("{:.0}-{:.0} Hz", self.low_hz, self.high_hz),
+        }
+    }
+}
+
+/// Keeps only frequencies within `ranges` in samples
+pub(crate) fn isolate(samples: &[f32], sample_rate: u32, ranges: &[(f32, f32)]) -> Vec<f32> {
+    let window_size = ((sample_rate as f32 * WINDOW_SEC) as usize).next_power_of_two();
+    let hop = window_size / OVERLAP_FACTOR;
+    let mask = mask(
+        ranges,
+        window_size / 2 + 1,
+        sample_rate as f32 / window_size as f32,
+    );
+
+    stft::filter(samples, window_size, hop, |spectra| {
+        for spectrum in spectra {
+            for (bin, gain) in spectrum.iter_mut().zip(&mask) {
+                *bin *= *gain;
+            }
+        }
+    })
+}
+
+/// Gains of `bins` bins by their distance to the nearest range. A sine spreads over the Hann main
+/// lobe, so bins within a bin of the range are kept and the next bin fades out.
+fn mask(ranges: &[(f32, f32)], bins: usize, bin_hz: f32) -> Vec<f32> {
+    (0..bins)
+        .map(|bin| {
+            let hz = bin as f32 * bin_hz;
+            let distance = ranges
+                .iter()
+                .map(|(low, high)| (low - hz).max(hz - high).max(0.0) / bin_hz)
+                .fold(f32::INFINITY, f32::min);
+            let fade = ((distance - KEPT_BINS) / (FADED_BINS - KEPT_BINS)).clamp(0.0, 1.0);
+            (fade * std::f32::consts::FRAC_PI_2).cos().powi(2)
+        })
+        .collect()
+}
+
+/// Selects keys by dragging over the spectrum with Shift held
+pub(crate) fn isolation_input(
+    mut contexts: EguiContexts,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut isolation: ResMut<Isolation>,
+    spectrum: Query<(&Transform, &Sprite), With<Spectrum>>,
+    keyboard: Query<&Transform, With<Keyboard>>,
+) {
+    if mouse_button_input.just_released(MouseButton::Left) {
+        isolation.drag_start = None;
+    }
+
+    let (Ok((spectrum_transform, spectrum_sprite)), Ok(keyboard_transform)) =
+        (spectrum.get_single(), keyboard.get_single())
+    else {
+        return;
+    };
+    let Some(cursor_pos) = crate::cursor_world_pos(windows.single()) else {
+        return;
+    };
+    // Keys are resolved at the top of the keyboard, where the black keys are
+    let Some(key) = crate::keyboard_pos_to_key(Vec2::new(
+        cursor_pos.x - keyboard_transform.translation.x,
+        crate::KEYBOARD_SIZE.y / 2.0,
+    )) else {
+        return;
+    };
+
+    // Continue dragging even if the cursor left the spectrum
+    if let Some(start) = isolation.drag_start {
+        isolation.first_key = start.min(key);
+        isolation.last_key = start.max(key);
+        return;
+    }
+
+    let spectrum_size = spectrum_sprite.custom_size.unwrap_or_default();
+    let in_spectrum = (cursor_pos - spectrum_transform.translation.xy())
+        .abs()
+        .cmplt(spectrum_size / 2.0)
+        .all();
+    if in_spectrum
+        && !contexts.ctx_mut().is_pointer_over_area()
+        && keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+        && mouse_button_input.just_pressed(MouseButton::Left)
+    {
+        isolation.enabled = true;
+        isolation.band = Band::Keys;
+        isolation.drag_start = Some(key);
+        isolation.first_key = key;
+        isolation.last_key = key;
+    }
+}
+
+/// The panel with the selection and its export
+pub(crate) fn isolation_ui(
+    mut contexts: EguiContexts,
+    mut isolation: ResMut<Isolation>,
+    mut source: ResMut<FftSource>,
+    config: Res<FftConfig>,
+    mut ev_update_spectrum: EventWriter<UpdateSpectrum>,
+) {
+    egui::Window::new("Band Isolation")
+        .default_open(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.checkbox(
+                &mut isolation.enabled,
+                "Show (Shift+drag over the spectrum)",
+            );
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut isolation.band, Band::Keys, "Keys");
+                ui.radio_value(&mut isolation.band, Band::Frequencies, "Frequencies");
+            });
+            let key_name = |key: f64, _| chroma::key_name(key as u8);
+            ui.horizontal(|ui| match isolation.band {
+                Band::Keys => {
+                    let max_key = KEYS_COUNT as u8 - 1;
+                    ui.add(
+                        egui::DragValue::new(&mut isolation.first_key)
+                            .clamp_range(0..=max_key)
+                            .custom_formatter(key_name),
+                    );
+                    ui.label("to");
+                    ui.add(
+                        egui::DragValue::new(&mut isolation.last_key)
+                            .clamp_range(0..=max_key)
+                            .custom_formatter(key_name),
+                    );
+                    ui.checkbox(&mut isolation.with_partials, "With partials");
+                }
+                Band::Frequencies => {
+                    let nyquist = source.sample_rate as f32 / 2.0;
+                    ui.add(
+                        egui::DragValue::new(&mut isolation.low_hz)
+                            .clamp_range(0.0..=nyquist)
+                            .suffix(" Hz"),
+                    );
+                    ui.label("to");
+                    ui.add(
+                        egui::DragValue::new(&mut isolation.high_hz)
+                            .clamp_range(0.0..=nyquist)
+                            .suffix(" Hz"),
+                    );
+                }
+            });
+
+            ui.horizontal(|ui| {
+                let export = ui.button("Export isolated audio").clicked();
+                let load = ui.button("Load as source").clicked();
+                if !export && !load {
+                    return;
+                }
+                let ranges =
+                    isolation.ranges(|key| config.key_frequency(key), &config.inharmonicity);
+                let isolated = isolate(
+                    source.analysed_samples(&config),
+                    source.sample_rate,
+                    &ranges,
+                );
+                if export {
+                    let path = source.export_path("isolated.wav");
+                    match std::fs::File::create(&path).and_then(|file| {
+                        wav::write_wav(std::io::BufWriter::new(file), &isolated, source.sample_rate)
+                    }) {
+                        Ok(()) => info!("Isolated audio is exported to {path:?}"),
+                        Err(err) => {
+                            error!("Failed to export isolated audio to {path:?}: {err:?}")
+                        }
+                    }
+                }
+                if load {
+                    // Samples outside the analysed range are silenced
+                    let range = source.analysed_range(&config);
+                    source.data.fill(0.0);
+                    source.data[range].copy_from_slice(&isolated);
+                    source.name = format!("{} ({})", source.name, isolation.label());
+                    ev_update_spectrum.send(UpdateSpectrum);
+                }
+            });
+        });
+}
+
+/// Draws the selection over the spectrum
+pub(crate) fn isolation_overlay(
+    mut contexts: EguiContexts,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    spectrum: Query<(&Transform, &Sprite), With<Spectrum>>,
+    keyboard: Query<&Transform, With<Keyboard>>,
+    isolation: Res<Isolation>,
+    config: Res<FftConfig>,
+    view: Res<MainView>,
+) {
+    if !isolation.enabled || *view != MainView::Spectrum {
+        return;
+    }
+    let (Ok((spectrum_transform, spectrum_sprite)), Ok(keyboard_transform)) =
+        (spectrum.get_single(), keyboard.get_single())
+    else {
+        return;
+    };
+    let window = windows.single();
+    // from the world coordinates with (0,0) in the center to egui's with (0,0) in the top left corner
+    let to_screen =
+        |x: f32, y: f32| egui::Pos2::new(x + window.width() / 2.0, window.height() / 2.0 - y);
+    // the position of a fractional key, between centers of adjacent keys
+    let key_x = |key: f32| {
+        let key = key.clamp(0.0, KEYS_COUNT as f32 - 1.0);
+        let (left, right) = (key.floor() as u8, key.ceil() as u8);
+        let (left_x, right_x) = (
+            crate::key_to_keyboard_pos_x(left),
+            crate::key_to_keyboard_pos_x(right),
+        );
+        keyboard_transform.translation.x + left_x + (right_x - left_x) * key.fract()
+    };
+    let (first_key, last_key) = match isolation.band {
+        Band::Keys => (
+            isolation.first_key.min(isolation.last_key) as f32 - 0.5,
+            isolation.first_key.max(isolation.last_key) as f32 + 0.5,
+        ),
+        Band::Frequencies => {
+            let key = |hz: f32| 12.0 * (hz / config.key_frequency(0)).log2();
+            (
+                key(isolation.low_hz.min(isolation.high_hz)),
+                key(isolation.low_hz.max(isolation.high_hz)),
+            )
+        }
+    };
+
+    let spectrum_size = spectrum_sprite.custom_size.unwrap_or_default();
+    let bottom = spectrum_transform.translation.y - spectrum_size.y / 2.0;
+    contexts
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background())
+        .rect_filled(
+            egui::Rect::from_two_pos(
+                to_screen(key_x(first_key), bottom),
+                to_screen(key_x(last_key), bottom + spectrum_size.y),
+            ),
+            0.0,
+            egui::Color32::from_rgba_unmultiplied(80, 160, 255, 40),
+        );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn ranges_test() {
+        let key_frequency = |key: u8| 440.0 * 2.0f32.powf((key as f32 - 48.0) / 12.0);
+        let inharmonicity = Inharmonicity::default();
+        let mut isolation = Isolation {
+            first_key: 49,
+            last_key: 48,
+            with_partials: false,
+            ..Default::default()
+        };
+        let ranges = isolation.ranges(key_frequency, &inharmonicity);
+        assert_eq!(ranges.len(), 2);
+        assert!((ranges[0].0 - 427.47).abs() < 0.01, "{:?}", ranges[0]);
+        assert!((ranges[0].1 - 452.89).abs() < 0.01, "{:?}", ranges[0]);
+
+        isolation.with_partials = true;
+        let ranges = isolation.ranges(key_frequency, &inharmonicity);
+        assert_eq!(ranges.len(), 2 * PARTIALS as usize);
+        // partials are stretched by the inharmonicity
+        assert!(ranges[1].0 > 880.0 / 2.0f32.powf(1.0 / 24.0));
+
+        isolation.band = Band::Frequencies;
+        isolation.low_hz = 300.0;
+        isolation.high_hz = 100.0;
+        assert_eq!(
+            isolation.ranges(key_frequency, &inharmonicity),
+            vec![(100.0, 300.0)]
+        );
+    }
+
+    #[test]
+    fn mask_test() {
+        // a single frequency keeps its neighbour bins, the fade starts a bin away from a range
+        let mask = mask(&[(10.0, 10.0), (65.0, 70.0)], 9, 10.0);
+        let expected = [1.0, 1.0, 1.0, 0.0, 0.0, 0.5, 1.0, 1.0, 1.0];
+        for (gain, expected) in mask.iter().zip(expected) {
+            assert!((gain - expected).abs() < 1e-6, "{mask:?}");
+        }
+    }
+
+    #[test]
+    fn isolate_test() {
+        let sample_rate = 8000;
+        let sine = |hz: f32, i: usize| (2.0 * std::f32::consts::PI * hz * i as f32 / 8000.0).sin();
+        let bass: Vec<f32> = (0..sample_rate * 2).map(|i| 0.5 * sine(110.0, i)).collect();
+        let mixed: Vec<f32> = bass
+            .iter()
+            .enumerate()
+            .map(|(i, bass)| bass + 0.5 * sine(880.0, i))
+            .collect();
+
+        let isolated = isolate(&mixed, sample_rate as u32, &[(50.0, 200.0)]);
+        assert_eq!(isolated.len(), mixed.len());
+        // abrupt starts and ends of sines smear over all frequencies
+        let range = 2000..isolated.len() - 2000;
+        let error = isolated[range.clone()]
+            .iter()
+            .zip(&bass[range])
+            .map(|(i, b)| (i - b).abs())
+            .fold(0.0, f32::max);
+        assert!(error < 0.05, "{error}");
+    }
+
+    #[test]
+    fn isolate_bass_keys_test() {
+        // bass bands are narrower than bins of the 8192 samples window at 48 kHz
+        let sample_rate = 48000;
+        let key_frequency = |key: u8| 27.5 * 2.0f32.powf(key as f32 / 12.0);
+        let ranges = Isolation::default().ranges(key_frequency, &Inharmonicity::default());
+        let sine = |hz: f32, i: usize| {
+            (2.0 * std::f32::consts::PI * hz * i as f32 / sample_rate as f32).sin()
+        };
+        // A0, C1 and A6, which is above partials of the bass register
+        let (a0, c1, a6) = (key_frequency(0), key_frequency(3), key_frequency(72));
+        let mixed: Vec<f32> = (0..sample_rate as usize * 2)
+            .map(|i| 0.3 * (sine(a0, i) + sine(c1, i) + sine(a6, i)))
+            .collect();
+
+        let isolated = isolate(&mixed, sample_rate, &ranges);
+        let middle = &isolated[24000..72000];
+        let magnitude = |hz| crate::goertzel::goertzel(middle, sample_rate, hz);
+        assert!(magnitude(a0) > 0.27, "{}", magnitude(a0));
+        assert!(magnitude(c1) > 0.27, "{}", magnitude(c1));
+        assert!(magnitude(a6) < 0.01, "{}", magnitude(a6));
+    }
+}
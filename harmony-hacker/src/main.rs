@@ -2,19 +2,45 @@ use self::overlap_chunks::OverlapChunksExt;
 use anyhow::Result;
 use bevy::{
     prelude::*,
-    render::render_resource::{
-        Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    render::{
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        texture::ImageSampler,
     },
     sprite::MaterialMesh2dBundle,
     window::PrimaryWindow,
 };
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
 use realfft::RealFftPlanner;
+use std::{borrow::Cow, path::PathBuf};
 use symphonia::core::audio::{AudioBufferRef, Signal};
 
 mod audio;
+mod beat;
+mod chord;
+mod chroma;
+mod detuning;
 mod goertzel;
+mod harmonics;
+mod hpss;
+mod inharmonicity;
+mod isolation;
+mod markers;
+mod multi_resolution;
+mod nmf;
+mod notes;
+mod onset;
 mod overlap_chunks;
+mod piano_roll;
+mod reassignment;
+mod scala;
+mod score;
+mod stft;
+mod temperament;
+mod tonality;
+mod tuning;
+mod wav;
 mod window_fn;
 
 /// White key dimensions
@@ -34,6 +60,12 @@ const BLACK_KEY_SIZE: Vec2 = Vec2 {
     y: 90.0,
 };
 
+/// For black keys we split the octave (7 white keys) into 12 slots and fill them according to the mask
+/// https://bootcamp.uxdesign.cc/drawing-a-flat-piano-keyboard-in-illustrator-de07c74a64c6
+const OCTAVE_MASK_BLACK: [bool; 12] = [
+    false, true, false, true, false, false, true, false, true, false, true, false,
+];
+
 /// The size of the keyboard
 const KEYBOARD_SIZE: Vec2 = Vec2 {
     x: WHITE_KEY_SIZE.x + (WHITE_KEYS_COUNT - 1) as f32 * (WHITE_KEY_SIZE.x + WHITE_KEYS_SPACE),
@@ -42,6 +74,10 @@ const KEYBOARD_SIZE: Vec2 = Vec2 {
 
 /// The frequency of the highest note in the piano, C8
 const MAX_FREQ: f32 = 4186.01;
+/// The widest spectrum image, the common limit of texture sizes
+const MAX_SPECTRUM_WIDTH: usize = 8192;
+/// The longest spectrum image, a row per frame, limited as the width
+const MAX_SPECTRUM_ROWS: usize = MAX_SPECTRUM_WIDTH;
 /// The frequency of the lowest note in the piano, A0
 const _MIN_FREQ: f32 = 27.5000;
 
@@ -51,17 +87,40 @@ fn main() {
         .add_plugins(EguiPlugin)
         .init_resource::<FftSource>()
         .init_resource::<FftConfig>()
+        .init_resource::<Transcription>()
+        .init_resource::<MainView>()
+        .init_resource::<detuning::DetuningReport>()
+        .init_resource::<nmf::LearnedTemplates>()
+        .init_resource::<isolation::Isolation>()
         .add_event::<PlayNote>()
         .add_event::<UpdateSpectrum>()
-        .add_systems(Startup, (setup, setup_piano_keys))
+        .add_systems(
+            Startup,
+            (
+                setup,
+                setup_piano_keys,
+                piano_roll::setup_piano_roll,
+                markers::setup_markers,
+            ),
+        )
         .add_systems(
             Update,
             (
                 file_drop,
                 egui_ui,
+                score_ui,
                 update_spectrum,
                 piano_keyboard,
                 play_note,
+                (piano_roll::piano_roll_input, piano_roll::update_piano_roll).chain(),
+                markers::update_markers,
+                markers::chord_lane_ui,
+                detuning::detuning_chart_ui,
+                (
+                    isolation::isolation_input,
+                    isolation::isolation_ui,
+                    isolation::isolation_overlay,
+                ),
             ),
         )
         .run();
@@ -70,6 +129,15 @@ fn main() {
 #[derive(Component)]
 struct Spectrum;
 
+/// What is shown in the area of the spectrum above the keyboard
+#[derive(Clone, Copy, Default, PartialEq, Resource)]
+enum MainView {
+    #[default]
+    Spectrum,
+    /// The score of the transcribed notes with time going from left to right
+    Score,
+}
+
 fn setup(mut commands: Commands, windows: Query<&Window, With<PrimaryWindow>>) {
     commands.spawn(Camera2dBundle::default());
 
@@ -135,11 +203,6 @@ fn setup_piano_keys(
         key_pos += WHITE_KEYS_STEP;
     }
 
-    // For black keys we split the octave (7 white keys) into 12 slots and fill them according to the mask
-    // https://bootcamp.uxdesign.cc/drawing-a-flat-piano-keyboard-in-illustrator-de07c74a64c6
-    let octave_mask_black = [
-        false, true, false, true, false, false, true, false, true, false, true, false,
-    ];
     let black_key_shape = meshes.add(Rectangle::from_size(BLACK_KEY_SIZE));
     let black_key_material = materials.add(Color::BLACK);
 
@@ -147,8 +210,8 @@ fn setup_piano_keys(
     // which we achieve by offsetting the iteration over mask.
     let start_pos = 2.0 * WHITE_KEYS_STEP - KEYBOARD_SIZE.x / 2.0 + BLACK_KEYS_SLOT_SIZE / 2.0;
     for i in -3..83 {
-        let octave_key = (octave_mask_black.len() as isize + i) as usize % octave_mask_black.len();
-        if octave_mask_black[octave_key] {
+        let octave_key = (OCTAVE_MASK_BLACK.len() as isize + i) as usize % OCTAVE_MASK_BLACK.len();
+        if OCTAVE_MASK_BLACK[octave_key] {
             commands
                 .spawn(MaterialMesh2dBundle {
                     mesh: black_key_shape.clone().into(),
@@ -196,6 +259,30 @@ fn keyboard_pos_to_key(pos: Vec2) -> Option<u8> {
     Some(key)
 }
 
+/// Resolve a key number 0..88 to the x position of the key center (in keyboard coordinates)
+fn key_to_keyboard_pos_x(key: u8) -> f32 {
+    // The keyboard starts from A0 key, while the mask starts from C
+    let is_black = |key: u8| OCTAVE_MASK_BLACK[(key as usize + 9) % OCTAVE_MASK_BLACK.len()];
+    if is_black(key) {
+        // Black keys are placed into slots in the same way as in `setup_piano_keys`
+        let start_pos = 2.0 * WHITE_KEYS_STEP - KEYBOARD_SIZE.x / 2.0 + BLACK_KEYS_SLOT_SIZE / 2.0;
+        start_pos + (key as f32 - 3.0) * BLACK_KEYS_SLOT_SIZE
+    } else {
+        let white_keys_before = (0..key).filter(|key| !is_black(*key)).count();
+        -KEYBOARD_SIZE.x / 2.0 + WHITE_KEY_SIZE.x / 2.0 + white_keys_before as f32 * WHITE_KEYS_STEP
+    }
+}
+
+/// Transform the cursor position from (0,0) in top left corner to the world coordinates with (0,0) in the center
+fn cursor_world_pos(window: &Window) -> Option<Vec2> {
+    window.cursor_position().map(|cursor_pos| {
+        Vec2::new(
+            cursor_pos.x - window.width() / 2.0,
+            window.height() / 2.0 - cursor_pos.y,
+        )
+    })
+}
+
 #[derive(Event)]
 struct PlayNote {
     key: u8,
@@ -208,14 +295,7 @@ fn piano_keyboard(
     mut ev_play_note: EventWriter<PlayNote>,
 ) {
     if mouse_button_input.just_pressed(MouseButton::Left) {
-        let window = windows.single();
-        if let Some(cursor_pos) = window.cursor_position() {
-            // Transform from (0,0) in top right corner to the world coordinates with (0,0) in the center
-            let cursor_pos = Vec2::new(
-                cursor_pos.x - window.width() / 2.0,
-                window.height() / 2.0 - cursor_pos.y,
-            );
-
+        if let Some(cursor_pos) = cursor_world_pos(windows.single()) {
             // Check if the cursor is in the keyboard
             for transform in keyboard.iter() {
                 let cursor_pos = cursor_pos - transform.translation.xy();
@@ -232,14 +312,15 @@ fn piano_keyboard(
 fn play_note(
     mut ev_play_note: EventReader<PlayNote>,
     mut fft_source: ResMut<FftSource>,
+    fft_config: Res<FftConfig>,
     mut ev_update_spectrum: EventWriter<UpdateSpectrum>,
 ) {
     for ev in ev_play_note.read() {
-        // The key number 49 (48 with zero-based index) is the A4 key with 440 Hz frequency
-        let freq = 440.0 * 2.0f64.powf((ev.key as f64 - 48.0) / 12.0);
+        let freq = fft_config.key_frequency(ev.key) as f64;
         info!("Playing note: {} with frequency: {}", ev.key, freq);
 
         fft_source.name = format!("Note: {freq:.2} Hz");
+        fft_source.path = None;
         fft_source.sample_rate = 48000;
 
         // Reuse the buffer for the new data
@@ -253,9 +334,11 @@ fn play_note(
     }
 }
 
-#[derive(Resource)]
+#[derive(Clone, Resource)]
 struct FftSource {
     name: String,
+    /// The file the source was loaded from, if any
+    path: Option<PathBuf>,
     sample_rate: u32,
     data: Vec<f32>,
 }
@@ -264,16 +347,71 @@ impl Default for FftSource {
     fn default() -> Self {
         Self {
             name: Default::default(),
+            path: None,
             sample_rate: 48000,
             data: Vec::with_capacity(48000 * 120),
         }
     }
 }
 
+impl FftSource {
+    /// Samples in the range selected by `offset_sec` and `duration_sec`
+    fn analysed_samples(&self, config: &FftConfig) -> &[f32] {
+        &self.data[self.analysed_range(config)]
+    }
+
+    /// Samples of the note, notes added to the piano roll may lie beyond the loaded samples
+    fn note_samples(&self, note: &notes::Note) -> &[f32] {
+        let sample_rate = self.sample_rate as f32;
+        let from = ((note.start_sec * sample_rate) as usize).min(self.data.len());
+        let to = (((note.start_sec + note.duration_sec) * sample_rate) as usize)
+            .clamp(from, self.data.len());
+        &self.data[from..to]
+    }
+
+    fn analysed_range(&self, config: &FftConfig) -> std::ops::Range<usize> {
+        let offset = self
+            .data
+            .len()
+            .min((config.offset_sec * self.sample_rate) as usize);
+        let end = self
+            .data
+            .len()
+            .min(((config.offset_sec + config.duration_sec) * self.sample_rate) as usize);
+        offset..end
+    }
+
+    /// The source as it's analysed: with analysed samples replaced by their harmonic component
+    /// if only it is analysed
+    fn preprocessed(&self, config: &FftConfig) -> Cow<'_, Self> {
+        if !config.harmonic_only {
+            return Cow::Borrowed(self);
+        }
+        let range = self.analysed_range(config);
+        let mut source = self.clone();
+        source.data[range.clone()]
+            .copy_from_slice(&hpss::harmonic(&self.data[range], self.sample_rate));
+        Cow::Owned(source)
+    }
+
+    /// A path for exporting analysis results next to the source file with the given extension
+    fn export_path(&self, extension: &str) -> PathBuf {
+        self.path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("harmony-hacker"))
+            .with_extension(extension)
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 enum Algorithm {
     Fft,
     Goertzel,
+    /// Key activations from the non-negative matrix factorisation of the FFT spectrogram
+    Nmf,
+    /// The FFT spectrogram with the Hann window and the energy reassigned to the instantaneous
+    /// frequency and the group delay
+    Reassigned,
 }
 
 #[derive(Clone, Copy, Default, PartialEq)]
@@ -281,13 +419,76 @@ enum WindowFunction {
     #[default]
     None,
     Hann,
+    Hamming,
+    Blackman,
+    BlackmanHarris,
+    Nuttall,
+    FlatTop,
+    Kaiser {
+        beta: f32,
+    },
+    Gaussian {
+        sigma: f32,
+    },
+    Tukey {
+        alpha: f32,
+    },
+    Bartlett,
+    Welch,
 }
 
-#[derive(Clone, Copy, Default, PartialEq)]
-enum Overlapping {
-    #[default]
-    None,
-    P50,
+impl WindowFunction {
+    fn window(&self, size: usize, symmetry: window_fn::Symmetry) -> Vec<f32> {
+        match symmetry {
+            window_fn::Symmetry::Symmetric => self.symmetric_window(size),
+            window_fn::Symmetry::Periodic => {
+                window_fn::periodic(size, |size| self.symmetric_window(size))
+            }
+        }
+    }
+
+    fn symmetric_window(&self, size: usize) -> Vec<f32> {
+        match *self {
+            WindowFunction::None => vec![1.0; size],
+            WindowFunction::Hann => window_fn::hann(size),
+            WindowFunction::Hamming => window_fn::hamming(size),
+            WindowFunction::Blackman => window_fn::blackman(size),
+            WindowFunction::BlackmanHarris => window_fn::blackman_harris(size),
+            WindowFunction::Nuttall => window_fn::nuttall(size),
+            WindowFunction::FlatTop => window_fn::flat_top(size),
+            WindowFunction::Kaiser { beta } => window_fn::kaiser(size, beta),
+            WindowFunction::Gaussian { sigma } => window_fn::gaussian(size, sigma),
+            WindowFunction::Tukey { alpha } => window_fn::tukey(size, alpha),
+            WindowFunction::Bartlett => window_fn::bartlett(size),
+            WindowFunction::Welch => window_fn::welch(size),
+        }
+    }
+}
+
+/// The time between the starts of adjacent frames
+#[derive(Clone, Copy, PartialEq)]
+enum Hop {
+    /// The fraction of the window shared by adjacent frames
+    Overlap(f32),
+    /// The fixed time regardless of the window, down to a sample
+    Milliseconds(f32),
+}
+
+impl Default for Hop {
+    fn default() -> Self {
+        Hop::Overlap(0.0)
+    }
+}
+
+impl Hop {
+    /// The hop in samples, frames can't be further apart than the window
+    fn samples(&self, window_size: usize, sample_rate: u32) -> usize {
+        let hop = match *self {
+            Hop::Overlap(overlap) => (1.0 - overlap) * window_size as f32,
+            Hop::Milliseconds(ms) => ms * sample_rate as f32 / 1000.0,
+        };
+        (hop.round() as usize).clamp(1, window_size.max(1))
+    }
 }
 
 #[derive(Clone, PartialEq, Resource)]
@@ -295,9 +496,39 @@ struct FftConfig {
     resolution_hz: f32,
     duration_sec: u32,
     offset_sec: u32,
+    /// The frequency of A4 all key frequencies are derived from
+    reference_hz: f32,
+    /// The tuning system that maps keys to frequencies
+    temperament: temperament::Temperament,
+    /// Whether keys are stretched from the temperament to match inharmonic partials
+    stretch: inharmonicity::Stretch,
+    /// Inharmonicity of the piano, the model until estimated from the recording
+    inharmonicity: inharmonicity::Inharmonicity,
     algorithm: Algorithm,
+    /// How Goertzel magnitudes of keys are combined into key activations
+    key_activation: harmonics::KeyActivation,
+    /// Spectral templates of keys for the NMF
+    nmf_templates: nmf::TemplateSource,
+    /// Whether only the harmonic component of the source is analysed, without hammer strikes
+    harmonic_only: bool,
     window_function: WindowFunction,
-    overlapping: Overlapping,
+    window_symmetry: window_fn::Symmetry,
+    /// How the window is scaled, so magnitudes don't depend on the window
+    window_normalization: window_fn::Normalization,
+    hop: Hop,
+    /// The FFT size in window lengths, the window is padded with zeros to it
+    zero_padding: u32,
+    /// Whether the FFT size is rounded up to a power of two, which is the fastest one
+    fft_power_of_two: bool,
+    /// Whether the spectrum image is linearly interpolated between pixels
+    smooth_display: bool,
+    /// Whether Goertzel windows get shorter for higher octaves, see [`multi_resolution`]
+    multi_resolution: bool,
+    /// Whether Goertzel filters slide per sample instead of restarting for each frame
+    sliding_goertzel: bool,
+    /// How frames at the boundaries of the analysed range are padded
+    padding: overlap_chunks::Padding,
+    onset_method: onset::OnsetMethod,
 }
 
 impl Default for FftConfig {
@@ -306,9 +537,47 @@ impl Default for FftConfig {
             resolution_hz: 50.0,
             duration_sec: 90,
             offset_sec: 0,
+            reference_hz: tuning::STANDARD_REFERENCE_HZ,
+            temperament: Default::default(),
+            stretch: Default::default(),
+            inharmonicity: Default::default(),
             algorithm: Algorithm::Goertzel,
+            key_activation: Default::default(),
+            nmf_templates: Default::default(),
+            harmonic_only: false,
             window_function: Default::default(),
-            overlapping: Default::default(),
+            window_symmetry: Default::default(),
+            window_normalization: Default::default(),
+            hop: Default::default(),
+            zero_padding: 1,
+            fft_power_of_two: false,
+            smooth_display: true,
+            multi_resolution: false,
+            sliding_goertzel: false,
+            padding: Default::default(),
+            onset_method: Default::default(),
+        }
+    }
+}
+
+impl FftConfig {
+    /// The window of the configured function, symmetry and normalization
+    fn window(&self, size: usize) -> Vec<f32> {
+        window_fn::normalize(
+            self.window_function.window(size, self.window_symmetry),
+            self.window_normalization,
+        )
+    }
+
+    /// The frequency of the first partial of the key number 0..88 in the configured tuning
+    fn key_frequency(&self, key: u8) -> f32 {
+        let frequency = self.temperament.key_frequency(key, self.reference_hz);
+        match self.stretch {
+            inharmonicity::Stretch::None => frequency,
+            inharmonicity::Stretch::Railsback => {
+                let stretch_cents = self.inharmonicity.stretch_cents()[key as usize];
+                frequency * 2.0f32.powf(stretch_cents / 1200.0)
+            }
         }
     }
 }
@@ -316,9 +585,40 @@ impl Default for FftConfig {
 #[derive(Event)]
 struct UpdateSpectrum;
 
+/// Notes detected in the analysed range of the source
+#[derive(Default, Resource)]
+struct Transcription {
+    /// Notes of the automatic transcription
+    transcribed: Vec<notes::Note>,
+    /// Notes shown on the piano roll and the score, the transcribed ones with edits of the user
+    notes: Vec<notes::Note>,
+    /// Whether `notes` were edited, so that new transcriptions don't discard the edits
+    edited: bool,
+    /// Incremented whenever `notes` are replaced, so that the selection of old notes is dropped
+    notes_generation: u32,
+    /// Note onsets in seconds from the beginning of the source
+    onsets: Vec<f32>,
+    /// Tempo and beats in seconds from the beginning of the source, if detected
+    beats: Option<beat::Beats>,
+    /// Musical key of the analysed range, if detected
+    key: Option<tonality::KeyEstimate>,
+    /// Chords of the analysed range
+    chords: Vec<chord::ChordSegment>,
+}
+
+impl Transcription {
+    /// Replaces notes with the automatic transcription, discarding edits
+    fn reset_notes(&mut self) {
+        self.notes = self.transcribed.clone();
+        self.edited = false;
+        self.notes_generation += 1;
+    }
+}
+
 fn file_drop(
     mut dnd_evr: EventReader<FileDragAndDrop>,
     mut fft_source: ResMut<FftSource>,
+    mut fft_config: ResMut<FftConfig>,
     mut ev_update_spectrum: EventWriter<UpdateSpectrum>,
 ) {
     for ev in dnd_evr.read() {
@@ -327,6 +627,22 @@ fn file_drop(
             path_buf,
         } = ev
         {
+            // Scala tuning files change the temperament instead of the source
+            if let Some("scl" | "kbm") = path_buf.extension().and_then(|ext| ext.to_str()) {
+                match load_scala(path_buf, &fft_config.temperament) {
+                    Ok(temperament) => {
+                        info!("Loaded the temperament {temperament}");
+                        if let Some(reference_hz) = temperament.scala_reference_hz() {
+                            fft_config.reference_hz = reference_hz;
+                        }
+                        fft_config.temperament = temperament;
+                        ev_update_spectrum.send(UpdateSpectrum);
+                    }
+                    Err(err) => error!("Failed to load the tuning {path_buf:?}: {err:?}"),
+                }
+                continue;
+            }
+
             match audio::Decoder::new(path_buf) {
                 Ok(mut decoder) => {
                     fft_source.name = path_buf
@@ -334,6 +650,7 @@ fn file_drop(
                         .and_then(|name| name.to_str())
                         .unwrap_or_default()
                         .to_owned();
+                    fft_source.path = Some(path_buf.clone());
 
                     fft_source.sample_rate = decoder.sample_rate();
 
@@ -366,31 +683,457 @@ fn file_drop(
     }
 }
 
+/// Loads a Scala scale or a keyboard mapping, which replaces the one in the current temperament
+fn load_scala(
+    path: &std::path::Path,
+    current: &temperament::Temperament,
+) -> Result<temperament::Temperament> {
+    let text = std::fs::read_to_string(path)?;
+    let (scale, mapping) = match current {
+        temperament::Temperament::Scala { scale, mapping } => (scale.clone(), mapping.clone()),
+        // The keyboard mapping alone applies to the equal temperament
+        _ => (
+            scala::Scale {
+                description: "12-EDO".to_owned(),
+                pitches: (1..=12).map(|step| step as f32 * 100.0).collect(),
+            },
+            Default::default(),
+        ),
+    };
+    Ok(if path.extension().is_some_and(|ext| ext == "kbm") {
+        temperament::Temperament::Scala {
+            scale,
+            mapping: scala::KeyboardMapping::parse(&text)?,
+        }
+    } else {
+        temperament::Temperament::Scala {
+            scale: scala::Scale::parse(&text)?,
+            mapping,
+        }
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
 fn egui_ui(
     mut contexts: EguiContexts,
     mut config: ResMut<FftConfig>,
     source: Res<FftSource>,
+    mut transcription: ResMut<Transcription>,
+    mut detuning_report: ResMut<detuning::DetuningReport>,
+    mut learned_templates: ResMut<nmf::LearnedTemplates>,
     mut ev_update_spectrum: EventWriter<UpdateSpectrum>,
+    mut framing_info: Local<FramingInfo>,
+    mut view: ResMut<MainView>,
 ) {
     let prev = config.clone();
+    framing_info.update(&source, &config);
 
     egui::Window::new("FFT Config").show(contexts.ctx_mut(), |ui| {
-        ui.label(format!("Source: {}", source.name));
+        match &transcription.beats {
+            Some(beats) => ui.label(format!("Source: {} ({:.0} BPM)", source.name, beats.bpm)),
+            None => ui.label(format!("Source: {}", source.name)),
+        };
+        if let Some(estimate) = &transcription.key {
+            ui.label(format!(
+                "Key: {} (confidence {:.2})",
+                estimate.key, estimate.confidence
+            ));
+        }
+        if transcription.edited {
+            ui.horizontal(|ui| {
+                ui.label("Notes are edited");
+                if ui
+                    .button("Transcribe again")
+                    .on_hover_text(
+                        "Discards edits and shows the transcription of the current range",
+                    )
+                    .clicked()
+                {
+                    transcription.reset_notes();
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("View:");
+            ui.radio_value(&mut *view, MainView::Spectrum, "Spectrum");
+            ui.radio_value(&mut *view, MainView::Score, "Score");
+        });
         ui.label("Resolution (Hz):");
         ui.add(egui::Slider::new(&mut config.resolution_hz, 1.0..=50.0));
         ui.label("Duration (sec):");
         ui.add(egui::Slider::new(&mut config.duration_sec, 1..=120));
         ui.label("Offset (sec):");
         ui.add(egui::Slider::new(&mut config.offset_sec, 0..=90));
+        ui.label("Reference A4 (Hz):");
+        ui.horizontal(|ui| {
+            ui.add(egui::Slider::new(&mut config.reference_hz, 400.0..=470.0));
+            if ui.button("Estimate").clicked() {
+                let samples = source.analysed_samples(&config);
+                match tuning::estimate_reference(samples, source.sample_rate, config.reference_hz) {
+                    Some(reference_hz) => {
+                        info!("Estimated reference: {reference_hz:.2} Hz");
+                        config.reference_hz = reference_hz;
+                    }
+                    None => warn!("Failed to estimate the reference, no pitched sound found"),
+                }
+            }
+        });
+        ui.label("Temperament:");
+        for option in [
+            temperament::Temperament::Equal,
+            temperament::Temperament::Pythagorean,
+            temperament::Temperament::QuarterCommaMeantone,
+            temperament::Temperament::WerckmeisterIII,
+        ] {
+            let label = option.to_string();
+            ui.radio_value(&mut config.temperament, option, label);
+        }
+        ui.horizontal(|ui| {
+            let just = matches!(config.temperament, temperament::Temperament::Just { .. });
+            if ui.radio(just, "Just").clicked() && !just {
+                config.temperament = temperament::Temperament::Just { tonic: 0 };
+            }
+            if let temperament::Temperament::Just { tonic } = &mut config.temperament {
+                egui::ComboBox::from_id_source("just_tonic")
+                    .selected_text(chroma::pitch_class_name(*tonic, false))
+                    .show_ui(ui, |ui| {
+                        for pitch_class in 0..12 {
+                            let name = chroma::pitch_class_name(pitch_class, false);
+                            ui.selectable_value(tonic, pitch_class, name);
+                        }
+                    });
+            }
+        });
+        if matches!(config.temperament, temperament::Temperament::Scala { .. }) {
+            let _ = ui.radio(true, config.temperament.to_string());
+        } else {
+            ui.label("Drop a .scl or .kbm file for Scala tunings");
+        }
+        ui.label("Stretch:");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut config.stretch, inharmonicity::Stretch::None, "None");
+            ui.radio_value(
+                &mut config.stretch,
+                inharmonicity::Stretch::Railsback,
+                "Railsback",
+            );
+            if ui
+                .button(format!(
+                    "Estimate inharmonicity from {} notes",
+                    transcription.notes.len()
+                ))
+                .clicked()
+            {
+                config.inharmonicity =
+                    estimate_inharmonicity(&source, &config, &transcription.notes);
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui
+                .button(format!(
+                    "Measure detuning of {} notes",
+                    transcription.notes.len()
+                ))
+                .clicked()
+            {
+                let estimates = measure_notes(&source, &config, &transcription.notes);
+                *detuning_report =
+                    detuning::DetuningReport::new(&estimates, |key| config.key_frequency(key));
+                let notes: Vec<(u8, &[f32])> = transcription
+                    .notes
+                    .iter()
+                    .map(|note| (note.key, source.note_samples(note)))
+                    .collect();
+                detuning_report.track_keys(&notes, source.sample_rate);
+                info!("Measured detuning of {} keys", detuning_report.measured());
+            }
+            if ui
+                .button(format!("Export {} keys", detuning_report.measured()))
+                .clicked()
+            {
+                let path = source.export_path("detuning.csv");
+                match std::fs::File::create(&path)
+                    .and_then(|file| detuning::write_csv(file, &detuning_report))
+                {
+                    Ok(()) => info!("Detuning report is exported to {path:?}"),
+                    Err(err) => error!("Failed to export the detuning report to {path:?}: {err:?}"),
+                }
+            }
+        });
         ui.label("Algorithm:");
         ui.radio_value(&mut config.algorithm, Algorithm::Fft, "FFT");
         ui.radio_value(&mut config.algorithm, Algorithm::Goertzel, "Goertzel");
+        if config.algorithm == Algorithm::Goertzel {
+            ui.checkbox(
+                &mut config.multi_resolution,
+                "Multi-resolution (shorter windows above B2)",
+            );
+            ui.checkbox(
+                &mut config.sliding_goertzel,
+                "Sliding Goertzel (rectangular window, fast small hops)",
+            );
+        }
+        ui.radio_value(&mut config.algorithm, Algorithm::Nmf, "NMF");
+        ui.radio_value(
+            &mut config.algorithm,
+            Algorithm::Reassigned,
+            "Reassigned FFT",
+        );
+        if config.algorithm == Algorithm::Nmf {
+            ui.horizontal(|ui| {
+                ui.radio_value(
+                    &mut config.nmf_templates,
+                    nmf::TemplateSource::Harmonic,
+                    "Harmonic",
+                );
+                ui.add_enabled_ui(learned_templates.0.is_some(), |ui| {
+                    ui.radio_value(
+                        &mut config.nmf_templates,
+                        nmf::TemplateSource::Learned,
+                        "Learned",
+                    );
+                });
+                if ui
+                    .button(format!("Learn from {} notes", transcription.notes.len()))
+                    .clicked()
+                {
+                    let (frames, harmonic) = nmf_input(&source, &config);
+                    let framing = SpectrumFraming::new(&source, &config);
+                    learned_templates.0 = Some(nmf::Templates::learn(
+                        &frames,
+                        config.offset_sec as f32,
+                        framing.hop_sec(source.sample_rate),
+                        &transcription.notes,
+                        harmonic,
+                    ));
+                    config.nmf_templates = nmf::TemplateSource::Learned;
+                    ev_update_spectrum.send(UpdateSpectrum);
+                }
+            });
+        }
+        ui.label("Key Activation:");
+        ui.radio_value(
+            &mut config.key_activation,
+            harmonics::KeyActivation::Fundamental,
+            "Fundamental",
+        );
+        ui.radio_value(
+            &mut config.key_activation,
+            harmonics::KeyActivation::HarmonicSum,
+            "Harmonic Sum",
+        );
+        ui.radio_value(
+            &mut config.key_activation,
+            harmonics::KeyActivation::HarmonicProduct,
+            "Harmonic Product",
+        );
+        ui.radio_value(
+            &mut config.key_activation,
+            harmonics::KeyActivation::Sparse,
+            "Sparse Decomposition",
+        );
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut config.harmonic_only, "Harmonic component only (HPSS)");
+            if ui.button("Export analysed audio").clicked() {
+                let path = source.export_path("analysed.wav");
+                match std::fs::File::create(&path).and_then(|file| {
+                    wav::write_wav(
+                        std::io::BufWriter::new(file),
+                        source.preprocessed(&config).analysed_samples(&config),
+                        source.sample_rate,
+                    )
+                }) {
+                    Ok(()) => info!("Analysed audio is exported to {path:?}"),
+                    Err(err) => error!("Failed to export analysed audio to {path:?}: {err:?}"),
+                }
+            }
+        });
         ui.label("Window Function:");
-        ui.radio_value(&mut config.window_function, WindowFunction::None, "None");
-        ui.radio_value(&mut config.window_function, WindowFunction::Hann, "Hann");
+        for (option, label) in [
+            (WindowFunction::None, "None"),
+            (WindowFunction::Hann, "Hann"),
+            (WindowFunction::Hamming, "Hamming"),
+            (WindowFunction::Blackman, "Blackman"),
+            (WindowFunction::BlackmanHarris, "Blackman-Harris"),
+            (WindowFunction::Nuttall, "Nuttall"),
+            (WindowFunction::FlatTop, "Flat-top"),
+            (WindowFunction::Bartlett, "Bartlett"),
+            (WindowFunction::Welch, "Welch"),
+        ] {
+            ui.radio_value(&mut config.window_function, option, label);
+        }
+        ui.horizontal(|ui| {
+            let kaiser = matches!(config.window_function, WindowFunction::Kaiser { .. });
+            if ui.radio(kaiser, "Kaiser").clicked() && !kaiser {
+                config.window_function = WindowFunction::Kaiser { beta: 8.6 };
+            }
+            if let WindowFunction::Kaiser { beta } = &mut config.window_function {
+                ui.add(egui::Slider::new(beta, 0.0..=20.0).text("beta"));
+            }
+        });
+        ui.horizontal(|ui| {
+            let gaussian = matches!(config.window_function, WindowFunction::Gaussian { .. });
+            if ui.radio(gaussian, "Gaussian").clicked() && !gaussian {
+                config.window_function = WindowFunction::Gaussian { sigma: 0.4 };
+            }
+            if let WindowFunction::Gaussian { sigma } = &mut config.window_function {
+                ui.add(egui::Slider::new(sigma, 0.1..=0.5).text("sigma"));
+            }
+        });
+        ui.horizontal(|ui| {
+            let tukey = matches!(config.window_function, WindowFunction::Tukey { .. });
+            if ui.radio(tukey, "Tukey").clicked() && !tukey {
+                config.window_function = WindowFunction::Tukey { alpha: 0.5 };
+            }
+            if let WindowFunction::Tukey { alpha } = &mut config.window_function {
+                ui.add(egui::Slider::new(alpha, 0.0..=1.0).text("alpha"));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.radio_value(
+                &mut config.window_symmetry,
+                window_fn::Symmetry::Symmetric,
+                "Symmetric",
+            );
+            ui.radio_value(
+                &mut config.window_symmetry,
+                window_fn::Symmetry::Periodic,
+                "Periodic",
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Normalization:");
+            ui.radio_value(
+                &mut config.window_normalization,
+                window_fn::Normalization::None,
+                "None",
+            );
+            ui.radio_value(
+                &mut config.window_normalization,
+                window_fn::Normalization::Amplitude,
+                "Amplitude",
+            );
+            ui.radio_value(
+                &mut config.window_normalization,
+                window_fn::Normalization::Energy,
+                "Energy",
+            );
+        });
+        ui.label(format!(
+            "Coherent gain: {:.3}, ENBW: {:.2} bins",
+            framing_info.coherent_gain, framing_info.enbw
+        ));
+        ui.horizontal(|ui| {
+            ui.label("Padding:");
+            for (option, label) in [
+                (overlap_chunks::Boundary::Zero, "Zero"),
+                (overlap_chunks::Boundary::Reflect, "Reflect"),
+                (overlap_chunks::Boundary::Edge, "Edge"),
+                (overlap_chunks::Boundary::Drop, "Drop"),
+            ] {
+                ui.radio_value(&mut config.padding.boundary, option, label);
+            }
+        });
+        ui.checkbox(
+            &mut config.padding.centered,
+            "Centered frames (rows at frame centers)",
+        );
+        ui.horizontal(|ui| {
+            ui.label("Zero padding:");
+            for factor in [1, 2, 4, 8] {
+                ui.radio_value(&mut config.zero_padding, factor, format!("x{factor}"));
+            }
+        });
+        ui.checkbox(
+            &mut config.fft_power_of_two,
+            "Round FFT size to a power of two",
+        );
+        ui.label(format!(
+            "FFT size: {} ({:.2} Hz bins)",
+            framing_info.fft_size, framing_info.bin_hz
+        ));
+        ui.checkbox(&mut config.smooth_display, "Smooth display");
         ui.label("Overlapping:");
-        ui.radio_value(&mut config.overlapping, Overlapping::None, "None");
-        ui.radio_value(&mut config.overlapping, Overlapping::P50, "50%");
+        ui.horizontal(|ui| {
+            let overlap = matches!(config.hop, Hop::Overlap(_));
+            if ui.radio(overlap, "Overlap").clicked() && !overlap {
+                config.hop = Hop::Overlap(0.5);
+            }
+            if let Hop::Overlap(overlap) = &mut config.hop {
+                ui.add(
+                    egui::Slider::new(overlap, 0.0..=0.9375)
+                        .custom_formatter(|value, _| format!("{:.1}%", value * 100.0)),
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            let milliseconds = matches!(config.hop, Hop::Milliseconds(_));
+            if ui.radio(milliseconds, "Hop").clicked() && !milliseconds {
+                config.hop = Hop::Milliseconds(10.0);
+            }
+            // a sample is the shortest hop, which the sliding Goertzel handles fast, unless rows
+            // of the whole duration don't fit into the spectrum image
+            let min_hop_ms = SpectrumFraming::min_hop(&source, &config).max(1) as f32 * 1000.0
+                / source.sample_rate as f32;
+            if let Hop::Milliseconds(ms) = &mut config.hop {
+                ui.add(
+                    egui::DragValue::new(ms)
+                        .clamp_range(min_hop_ms..=1000.0)
+                        .speed(0.1)
+                        .suffix(" ms"),
+                );
+                let hop = Hop::Milliseconds(*ms).samples(usize::MAX, source.sample_rate);
+                ui.label(format!("{hop} samples"));
+            }
+        });
+        if !framing_info.cola {
+            ui.label("Not COLA: overlapped frames weigh samples unevenly")
+                .on_hover_text(
+                    "Checked for the periodic form of the window, the symmetric one is never \
+                     exactly COLA. Resynthesis (HPSS, band isolation) uses its own Hann frames \
+                     with 75% overlap.",
+                );
+        }
+        ui.label("Onset Detection:");
+        ui.radio_value(
+            &mut config.onset_method,
+            onset::OnsetMethod::SpectralFlux,
+            "Spectral Flux",
+        );
+        ui.radio_value(
+            &mut config.onset_method,
+            onset::OnsetMethod::HighFrequencyContent,
+            "High Frequency Content",
+        );
+        ui.radio_value(
+            &mut config.onset_method,
+            onset::OnsetMethod::ComplexDomain,
+            "Complex Domain",
+        );
+        if ui
+            .button(format!("Export {} onsets", transcription.onsets.len()))
+            .clicked()
+        {
+            let path = source.export_path("onsets.txt");
+            match std::fs::File::create(&path)
+                .and_then(|file| onset::write_timestamps(file, &transcription.onsets))
+            {
+                Ok(()) => info!("Onsets are exported to {path:?}"),
+                Err(err) => error!("Failed to export onsets to {path:?}: {err:?}"),
+            }
+        }
+        if ui
+            .button(format!("Export {} chords", transcription.chords.len()))
+            .clicked()
+        {
+            let path = source.export_path("lab");
+            match std::fs::File::create(&path)
+                .and_then(|file| chord::write_lab(file, &transcription.chords))
+            {
+                Ok(()) => info!("Chords are exported to {path:?}"),
+                Err(err) => error!("Failed to export chords to {path:?}: {err:?}"),
+            }
+        }
     });
 
     if prev != *config {
@@ -398,47 +1141,404 @@ fn egui_ui(
     }
 }
 
+/// Measures partials of each note around the frequency of its key, notes without enough partials are skipped
+fn measure_notes(
+    source: &FftSource,
+    config: &FftConfig,
+    notes: &[notes::Note],
+) -> Vec<(u8, inharmonicity::PartialsEstimate)> {
+    notes
+        .iter()
+        .filter_map(|note| {
+            let samples = source.note_samples(note);
+            let expected_hz = config.key_frequency(note.key);
+            if samples.len()
+                < inharmonicity::min_estimation_samples(source.sample_rate, expected_hz)
+            {
+                return None;
+            }
+            inharmonicity::estimate_partials(samples, source.sample_rate, expected_hz)
+                .map(|estimate| (note.key, estimate))
+        })
+        .collect()
+}
+
+/// Estimates inharmonicity per register from partials of transcribed notes and logs how far the
+/// recorded tuning deviates from the stretch model with the estimated inharmonicity
+fn estimate_inharmonicity(
+    source: &FftSource,
+    config: &FftConfig,
+    notes: &[notes::Note],
+) -> inharmonicity::Inharmonicity {
+    let estimates = measure_notes(source, config, notes);
+    let coefficients: Vec<(u8, f32)> = estimates
+        .iter()
+        .map(|(key, estimate)| (*key, estimate.coefficient))
+        .collect();
+    let estimated = inharmonicity::Inharmonicity::from_registers(&inharmonicity::register_medians(
+        &coefficients,
+    ));
+
+    let stretch = estimated.stretch_cents();
+    let deviations: Vec<(u8, f32)> = estimates
+        .iter()
+        .map(|(key, estimate)| {
+            let model_hz = config.temperament.key_frequency(*key, config.reference_hz)
+                * 2.0f32.powf(stretch[*key as usize] / 1200.0);
+            (*key, 1200.0 * (estimate.first_partial_hz / model_hz).log2())
+        })
+        .collect();
+    let deviations = inharmonicity::register_medians(&deviations);
+    for (register, deviation) in deviations.iter().enumerate() {
+        if let Some(deviation) = deviation {
+            let key = register as u8 * 12;
+            info!(
+                "Register A{register}: B = {:.2e} (model {:.2e}), {deviation:+.1} cents from the stretch model",
+                estimated.coefficient(key),
+                inharmonicity::model_coefficient(key),
+            );
+        }
+    }
+    info!("Estimated inharmonicity from {} notes", estimates.len());
+
+    estimated
+}
+
+/// Draws the score instead of the spectrum, covering the whole spectrum area
+fn score_ui(
+    mut contexts: EguiContexts,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    spectrum: Query<(&Transform, &Sprite), With<Spectrum>>,
+    config: Res<FftConfig>,
+    transcription: Res<Transcription>,
+    view: Res<MainView>,
+) {
+    if *view != MainView::Score {
+        return;
+    }
+    let Ok((spectrum_transform, spectrum_sprite)) = spectrum.get_single() else {
+        return;
+    };
+    let window = windows.single();
+    let size = spectrum_sprite.custom_size.unwrap_or_default();
+    // from the world coordinates with (0,0) in the center to egui's with (0,0) in the top left corner
+    let top_left = egui::Pos2::new(
+        spectrum_transform.translation.x - size.x / 2.0 + window.width() / 2.0,
+        window.height() / 2.0 - spectrum_transform.translation.y - size.y / 2.0,
+    );
+    let rect = egui::Rect::from_min_size(top_left, egui::Vec2::new(size.x, size.y));
+
+    egui::Area::new(egui::Id::new("score"))
+        .fixed_pos(top_left)
+        .order(egui::Order::Background)
+        .show(contexts.ctx_mut(), |ui| {
+            // the area covers the spectrum, so the piano roll underneath isn't edited by accident
+            ui.set_min_size(rect.size());
+            ui.set_max_width(rect.width());
+            ui.painter().rect_filled(rect, 0.0, egui::Color32::WHITE);
+            ui.add_space((rect.height() - score::SCORE_HEIGHT).max(0.0) / 2.0);
+
+            let beats = transcription.beats.as_ref();
+            score::ScoreView {
+                notes: &transcription.notes,
+                offset_sec: config.offset_sec as f32,
+                duration_sec: config.duration_sec as f32,
+                key_signature: transcription
+                    .key
+                    .map_or(0, |estimate| estimate.key.key_signature()),
+                // 120 BPM if the tempo is unknown
+                beat_sec: beats.map_or(0.5, |beats| 60.0 / beats.bpm),
+                beats: beats.map_or(&[], |beats| &beats.beats),
+                chord_segments: &transcription.chords,
+            }
+            .show(ui);
+        });
+}
+
 fn update_spectrum(
     mut ev_update_spectrum: EventReader<UpdateSpectrum>,
     fft_source: Res<FftSource>,
     fft_config: Res<FftConfig>,
     mut images: ResMut<Assets<Image>>,
     mut spectrum_spties: Query<&mut Handle<Image>, With<Spectrum>>,
+    mut transcription: ResMut<Transcription>,
+    learned_templates: Res<nmf::LearnedTemplates>,
 ) {
     for _ in ev_update_spectrum.read() {
+        let analysed_source = fft_source.preprocessed(&fft_config);
+        let key_frames = match fft_config.algorithm {
+            Algorithm::Nmf => nmf_key_frames(&analysed_source, &fft_config, &learned_templates),
+            Algorithm::Fft | Algorithm::Goertzel | Algorithm::Reassigned => {
+                let templates = harmonics::HarmonicTemplates::new(
+                    |key| fft_config.key_frequency(key),
+                    &fft_config.inharmonicity,
+                    MAX_FREQ,
+                );
+                // partials other than fundamentals are measured only if they are needed
+                let frequencies = match fft_config.key_activation {
+                    harmonics::KeyActivation::Fundamental => {
+                        &templates.frequencies()[..notes::KEYS_COUNT]
+                    }
+                    _ => templates.frequencies(),
+                };
+                harmonics::key_activations(
+                    &goertzel_frames(&analysed_source, &fft_config, frequencies),
+                    &templates,
+                    fft_config.key_activation,
+                )
+            }
+        };
+        let framing = SpectrumFraming::new(&fft_source, &fft_config);
+        let offset_sec = fft_config.offset_sec as f32;
+        let hop_sec = framing.hop_sec(fft_source.sample_rate);
+
+        // onsets are detected in the original signal, as hammer strikes mark them best
+        let onset_strength = onset::onset_strength(
+            fft_source.analysed_samples(&fft_config),
+            fft_source.sample_rate,
+            fft_config.onset_method,
+        );
+        let onsets = onset::detect_onsets(&onset_strength, &Default::default());
+        let mut onset_frames: Vec<usize> = onsets
+            .iter()
+            .map(|onset| (onset / hop_sec).round() as usize)
+            .collect();
+        onset_frames.dedup();
+        transcription.onsets = onsets.iter().map(|onset| offset_sec + onset).collect();
+        transcription.beats = beat::beats(&onset_strength).map(|beats| beat::Beats {
+            bpm: beats.bpm,
+            beats: beats.beats.iter().map(|beat| offset_sec + beat).collect(),
+        });
+
+        transcription.key = tonality::estimate_key(&chroma::summed_chroma(&key_frames));
+        transcription.chords =
+            chord::recognize_chords(&key_frames, offset_sec, hop_sec, NOTE_THRESHOLD);
+
+        transcription.transcribed = notes::transcribe(
+            &key_frames,
+            &onset_frames,
+            offset_sec,
+            hop_sec,
+            NOTE_THRESHOLD,
+        );
+        info!(
+            "Transcribed {} notes with {} onsets",
+            transcription.transcribed.len(),
+            transcription.onsets.len()
+        );
+        // edits are kept until the user asks to transcribe again, unless the source is new
+        if !transcription.edited || fft_source.is_changed() {
+            transcription.reset_notes();
+        }
+
         for mut handle in spectrum_spties.iter_mut() {
             *handle = match fft_config.algorithm {
-                Algorithm::Fft => build_spectrum_fft(&fft_source, &fft_config),
-                Algorithm::Goertzel => build_spectrum_goertzel(&fft_source, &fft_config),
+                Algorithm::Fft => build_spectrum_fft(
+                    &fft_frames(&analysed_source, &fft_config),
+                    framing.bins(fft_source.sample_rate),
+                    framing.rows,
+                ),
+                Algorithm::Reassigned => build_spectrum_fft(
+                    &reassigned_frames(&analysed_source, &fft_config),
+                    framing.bins(fft_source.sample_rate),
+                    framing.rows,
+                ),
+                Algorithm::Goertzel | Algorithm::Nmf => {
+                    build_spectrum_goertzel(&key_frames, framing.rows)
+                }
             }
-            .map(|image| images.add(image))
+            .map(|mut image| {
+                if !fft_config.smooth_display {
+                    image.sampler = ImageSampler::nearest();
+                }
+                images.add(image)
+            })
             .inspect_err(|err| error!("Failed to build spectrum: {:?}", err))
             .unwrap_or_default();
         }
     }
 }
 
-fn build_spectrum_fft(source: &FftSource, config: &FftConfig) -> Result<Image> {
-    let window_size = (source.sample_rate as f32 / config.resolution_hz) as usize;
-    info!("FFT window size: {}", window_size);
+/// The minimal Goertzel magnitude of a key to be considered as a played note
+const NOTE_THRESHOLD: f32 = 0.05;
+
+/// How the source is split into overlapping frames, one frame per spectrum row
+struct SpectrumFraming {
+    window_size: usize,
+    /// The window padded with zeros
+    fft_size: usize,
+    overlapping: usize,
+    rows: u32,
+}
+
+impl SpectrumFraming {
+    fn new(source: &FftSource, config: &FftConfig) -> Self {
+        let window_size = (source.sample_rate as f32 / config.resolution_hz) as usize;
+        // the image can't be wider than the texture limit, so the padding is limited too
+        let max_fft_size =
+            ((MAX_SPECTRUM_WIDTH - 1) as f32 * source.sample_rate as f32 / MAX_FREQ) as usize;
+        let padded_size = (window_size * config.zero_padding as usize)
+            .min(max_fft_size)
+            .max(window_size);
+        let fft_size = match padded_size.next_power_of_two() {
+            _ if !config.fft_power_of_two => padded_size,
+            size if size > max_fft_size && size / 2 >= window_size => size / 2,
+            size => size,
+        };
+        let hop = config
+            .hop
+            .samples(window_size, source.sample_rate)
+            .max(Self::min_hop(source, config))
+            .min(window_size);
+        let overlapping = window_size - hop;
+        // a row per frame of the analysed duration
+        let rows = config.padding.chunks_count(
+            (source.sample_rate * config.duration_sec) as usize,
+            window_size,
+            overlapping,
+        ) as u32;
+        Self {
+            window_size,
+            fft_size,
+            overlapping,
+            rows,
+        }
+    }
+
+    /// The shortest hop whose rows of the analysed duration fit into the spectrum image. With one
+    /// row spare for the last incomplete frame, the hop holds for any padding.
+    fn min_hop(source: &FftSource, config: &FftConfig) -> usize {
+        ((source.sample_rate * config.duration_sec) as usize).div_ceil(MAX_SPECTRUM_ROWS - 1)
+    }
+
+    /// The time between the starts of two adjacent frames
+    fn hop_sec(&self, sample_rate: u32) -> f32 {
+        (self.window_size - self.overlapping) as f32 / sample_rate as f32
+    }
+
+    /// The distance between FFT bins
+    fn bin_hz(&self, sample_rate: u32) -> f32 {
+        sample_rate as f32 / self.fft_size as f32
+    }
+
+    /// The number of FFT bins up to the highest note of the piano
+    fn bins(&self, sample_rate: u32) -> usize {
+        1 + (MAX_FREQ / self.bin_hz(sample_rate)) as usize
+    }
+}
+
+/// Properties of the framing shown in the panel. Windows can be thousands of samples long, so they
+/// are calculated only when the config changes rather than on every frame.
+#[derive(Default)]
+struct FramingInfo {
+    /// The sample rate and the config the properties are calculated for
+    calculated_for: Option<(u32, FftConfig)>,
+    /// Gains of the window at the current resolution
+    coherent_gain: f32,
+    enbw: f32,
+    fft_size: usize,
+    bin_hz: f32,
+    /// Whether the periodic form of the window is COLA with the hop
+    cola: bool,
+}
+
+impl FramingInfo {
+    fn update(&mut self, source: &FftSource, config: &FftConfig) {
+        if self
+            .calculated_for
+            .as_ref()
+            .is_some_and(|(sample_rate, calculated)| {
+                *sample_rate == source.sample_rate && calculated == config
+            })
+        {
+            return;
+        }
+
+        let framing = SpectrumFraming::new(source, config);
+        let window = config
+            .window_function
+            .window(framing.window_size, config.window_symmetry);
+        *self = Self {
+            calculated_for: Some((source.sample_rate, config.clone())),
+            coherent_gain: window_fn::coherent_gain(&window),
+            enbw: window_fn::enbw(&window),
+            fft_size: framing.fft_size,
+            bin_hz: framing.bin_hz(source.sample_rate),
+            cola: window_fn::is_cola(
+                &config
+                    .window_function
+                    .window(framing.window_size, window_fn::Symmetry::Periodic),
+                framing.window_size - framing.overlapping,
+            ),
+        };
+    }
+}
+
+/// Calculates FFT magnitudes of bins up to the highest note for each frame of the analysed range
+fn fft_frames(source: &FftSource, config: &FftConfig) -> Vec<Vec<f32>> {
+    let framing = SpectrumFraming::new(source, config);
+    let window_size = framing.window_size;
+    info!(
+        "FFT window size: {}, FFT size: {}",
+        window_size, framing.fft_size
+    );
 
     let mut real_planner = RealFftPlanner::<f32>::new();
-    let r2c = real_planner.plan_fft_forward(window_size);
-    // let mut input_buf = Vec::<f32>::with_capacity(fft_window_size);
+    let r2c = real_planner.plan_fft_forward(framing.fft_size);
     let mut input_buf = r2c.make_input_vec();
     let mut output_buf = r2c.make_output_vec();
     let mut scratch_buf = r2c.make_scratch_vec();
 
-    let spectrum_rows = source.sample_rate * config.duration_sec / window_size as u32;
-    let (overlapping, spectrum_rows) = match config.overlapping {
-        Overlapping::None => (0, spectrum_rows),
-        Overlapping::P50 => (window_size / 2, spectrum_rows * 2 - 1),
-    };
+    let window = config.window(window_size);
+    let bins = framing.bins(source.sample_rate);
+
+    let chunks = source.analysed_samples(config).padded_chunks(
+        window_size,
+        framing.overlapping,
+        config.padding,
+    );
+    chunks
+        .take(framing.rows as usize)
+        .map(|chunk| {
+            for ((input, sample), window) in input_buf.iter_mut().zip(chunk.iter()).zip(&window) {
+                *input = sample * window;
+            }
+            // the transform leaves garbage in its input, so the padding is zeroed for every frame
+            input_buf[window_size..].fill(0.0);
+
+            r2c.process_with_scratch(&mut input_buf, &mut output_buf, &mut scratch_buf)
+                .unwrap();
+            output_buf
+                .iter()
+                .take(bins)
+                .map(|value| value.norm())
+                .collect()
+        })
+        .collect()
+}
+
+/// Reassigned spectrogram of the analysed range with the same bins and rows as `fft_frames`
+fn reassigned_frames(source: &FftSource, config: &FftConfig) -> Vec<Vec<f32>> {
+    let framing = SpectrumFraming::new(source, config);
+    let chunks = source.analysed_samples(config).padded_chunks(
+        framing.window_size,
+        framing.overlapping,
+        config.padding,
+    );
+    reassignment::reassigned_frames(
+        chunks,
+        framing.window_size,
+        framing.fft_size,
+        framing.window_size - framing.overlapping,
+        framing.bins(source.sample_rate),
+        framing.rows as usize,
+    )
+}
 
+fn build_spectrum_fft(frames: &[Vec<f32>], bins: usize, spectrum_rows: u32) -> Result<Image> {
     // image related stuff
-    let bins_to_take = 1 + (MAX_FREQ / source.sample_rate as f32 * window_size as f32) as u32;
     let size = Extent3d {
-        width: bins_to_take,
+        width: bins as u32,
         height: spectrum_rows,
         ..default()
     };
@@ -457,26 +1557,8 @@ fn build_spectrum_fft(source: &FftSource, config: &FftConfig) -> Result<Image> {
         ..default()
     };
 
-    let window = match config.window_function {
-        WindowFunction::None => vec![1.0; window_size],
-        WindowFunction::Hann => window_fn::hann(window_size),
-    };
-
-    let offset = source
-        .data
-        .len()
-        .min((config.offset_sec * source.sample_rate) as usize);
-    let chunks = source.data[offset..].overlap_chunks(window_size, overlapping);
-    for chunk in chunks.take(spectrum_rows as usize) {
-        input_buf.copy_from_slice(chunk);
-        for (sample, window) in input_buf.iter_mut().zip(&window) {
-            *sample *= *window;
-        }
-
-        r2c.process_with_scratch(&mut input_buf, &mut output_buf, &mut scratch_buf)
-            .unwrap();
-        for value in output_buf.iter().take(bins_to_take as usize) {
-            let s = value.norm();
+    for frame in frames {
+        for s in frame {
             let s = s.max(1e-10); // Avoid taking the logarithm of zero
             let s = (s.log10() / 3.0).min(1.0); // convert to 0..60db range in 0..1
             let s = (s * 255.0) as u8;
@@ -490,16 +1572,160 @@ fn build_spectrum_fft(source: &FftSource, config: &FftConfig) -> Result<Image> {
     Ok(image)
 }
 
-fn build_spectrum_goertzel(source: &FftSource, config: &FftConfig) -> Result<Image> {
-    let window_size = (source.sample_rate as f32 / config.resolution_hz) as usize;
+/// Amplitude spectra for the NMF, so activations are comparable with Goertzel magnitudes,
+/// and harmonic templates with the same bins
+fn nmf_input(source: &FftSource, config: &FftConfig) -> (Vec<Vec<f32>>, nmf::Templates) {
+    let framing = SpectrumFraming::new(source, config);
+    // a sine wave of the unit amplitude has the peak of the half of the window sum
+    let window_sum: f32 = config.window(framing.window_size).iter().sum();
+    let frames = fft_frames(source, config)
+        .into_iter()
+        .map(|frame| {
+            frame
+                .iter()
+                .map(|magnitude| magnitude * 2.0 / window_sum)
+                .collect()
+        })
+        .collect();
+    let templates = nmf::Templates::harmonic(
+        framing.bins(source.sample_rate),
+        framing.bin_hz(source.sample_rate),
+        |key| config.key_frequency(key),
+        &config.inharmonicity,
+    );
+    (frames, templates)
+}
+
+/// Key activations decomposed from the FFT spectrogram
+fn nmf_key_frames(
+    source: &FftSource,
+    config: &FftConfig,
+    learned: &nmf::LearnedTemplates,
+) -> Vec<[f32; notes::KEYS_COUNT]> {
+    let (frames, harmonic) = nmf_input(source, config);
+    match (config.nmf_templates, &learned.0) {
+        (nmf::TemplateSource::Learned, Some(learned)) => {
+            // learned templates are the spectra of the piano, so they are kept as is
+            let templates = learned.resample(harmonic.bins, harmonic.bin_hz);
+            nmf::decompose(&frames, &templates, false)
+        }
+        _ => nmf::decompose(&frames, &harmonic, true),
+    }
+}
+
+/// Calculates Goertzel magnitudes at frequencies, e.g. of keys, for each frame of the analysed range
+fn goertzel_frames(source: &FftSource, config: &FftConfig, frequencies: &[f32]) -> Vec<Vec<f32>> {
+    let SpectrumFraming {
+        window_size,
+        overlapping,
+        rows,
+        ..
+    } = SpectrumFraming::new(source, config);
     info!("Goertzel window size: {}", window_size);
 
-    let spectrum_rows = source.sample_rate * config.duration_sec / window_size as u32;
-    let (overlapping, spectrum_rows) = match config.overlapping {
-        Overlapping::None => (0, spectrum_rows),
-        Overlapping::P50 => (window_size / 2, spectrum_rows * 2 - 1),
+    if config.multi_resolution {
+        // frames share centers with the frames of the full window
+        let hop = window_size - overlapping;
+        let offset = if config.padding.centered {
+            0
+        } else {
+            window_size / 2
+        };
+        return multi_resolution::frames(
+            source.analysed_samples(config),
+            source.sample_rate,
+            frequencies,
+            window_size,
+            |size| config.window(size),
+            (0..rows as usize).map(|frame| offset + frame * hop),
+        );
+    }
+
+    if config.sliding_goertzel {
+        return sliding_goertzel_frames(source, config, frequencies);
+    }
+
+    let window = config.window(window_size);
+
+    let mut states = frequencies
+        .iter()
+        .map(|frequency| goertzel::Goertzel::new(source.sample_rate, *frequency))
+        .collect::<Vec<_>>();
+    let chunks =
+        source
+            .analysed_samples(config)
+            .padded_chunks(window_size, overlapping, config.padding);
+    chunks
+        .take(rows as usize)
+        .map(|chunk| {
+            for sample in chunk.iter().zip(&window).map(|(s, w)| s * w) {
+                for state in states.iter_mut() {
+                    state.process(sample)
+                }
+            }
+
+            states
+                .iter_mut()
+                .map(|state| {
+                    let magnitude = state.magnitude(window_size as u32);
+                    state.reset();
+                    magnitude
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Goertzel frames with the sliding Goertzel, so the cost doesn't depend on the hop. The window
+/// function is ignored for the rectangular one and frames out of range are padded with zeros.
+fn sliding_goertzel_frames(
+    source: &FftSource,
+    config: &FftConfig,
+    frequencies: &[f32],
+) -> Vec<Vec<f32>> {
+    let SpectrumFraming {
+        window_size,
+        overlapping,
+        rows,
+        ..
+    } = SpectrumFraming::new(source, config);
+    let hop = window_size - overlapping;
+    let samples = source.analysed_samples(config);
+    let sample =
+        |i: isize| usize::try_from(i).map_or(0.0, |i| samples.get(i).copied().unwrap_or(0.0));
+
+    let mut states: Vec<goertzel::SlidingGoertzel> = frequencies
+        .iter()
+        .map(|frequency| {
+            goertzel::SlidingGoertzel::new(source.sample_rate, *frequency, window_size)
+        })
+        .collect();
+    // the first sample of the first frame
+    let start = if config.padding.centered {
+        -((window_size / 2) as isize)
+    } else {
+        0
     };
+    let mut next = start;
+    let mut frames = Vec::with_capacity(rows as usize);
+    for frame in 0..rows as usize {
+        let frame_end = start + (frame * hop + window_size) as isize;
+        for i in next..frame_end {
+            let (sample, dropped) = (sample(i), sample(i - window_size as isize));
+            for state in states.iter_mut() {
+                state.update(sample, dropped);
+            }
+        }
+        next = frame_end;
+        frames.push(states.iter().map(|state| state.magnitude()).collect());
+    }
+    frames
+}
 
+fn build_spectrum_goertzel(
+    key_frames: &[[f32; notes::KEYS_COUNT]],
+    spectrum_rows: u32,
+) -> Result<Image> {
     // image related stuff
     let size = Extent3d {
         width: 88 * 3 + 5,
@@ -521,36 +1747,14 @@ fn build_spectrum_goertzel(source: &FftSource, config: &FftConfig) -> Result<Ima
         ..default()
     };
 
-    let window = match config.window_function {
-        WindowFunction::None => vec![1.0; window_size],
-        WindowFunction::Hann => window_fn::hann(window_size),
-    };
-
-    let mut key_states = (0..88)
-        .map(|key| 440.0 * 2.0f64.powf((key as f64 - 48.0) / 12.0) as f32)
-        .map(|frequency| goertzel::Goertzel::new(source.sample_rate, frequency))
-        .collect::<Vec<_>>();
-    let offset = source
-        .data
-        .len()
-        .min((config.offset_sec * source.sample_rate) as usize);
-    let chunks = source.data[offset..].overlap_chunks(window_size, overlapping);
-    for chunk in chunks.take(spectrum_rows as usize) {
-        for sample in chunk.iter().zip(&window).map(|(s, w)| s * w) {
-            for state in key_states.iter_mut() {
-                state.process(sample)
-            }
-        }
-
+    for magnitudes in key_frames {
         image.data.push(0);
         image.data.push(0);
-        for state in key_states.iter_mut() {
-            let s = state.magnitude(window_size as u32);
-            let s = (s * 255.0) as u8;
+        for magnitude in magnitudes {
+            let s = (magnitude * 255.0) as u8;
             for _ in 0..3 {
                 image.data.push(s);
             }
-            state.reset();
         }
         image.data.push(0);
         image.data.push(0);
@@ -568,6 +1772,135 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn transcription_edits_test() {
+        let note = |key| notes::Note {
+            key,
+            start_sec: 1.0,
+            duration_sec: 0.5,
+        };
+        let mut transcription = Transcription {
+            transcribed: vec![note(48)],
+            ..Default::default()
+        };
+        transcription.reset_notes();
+        assert_eq!(transcription.notes, vec![note(48)]);
+        assert_eq!(transcription.notes_generation, 1);
+
+        // edits stay until notes are reset
+        transcription.notes.push(note(50));
+        transcription.edited = true;
+        transcription.transcribed = vec![note(52)];
+        assert_eq!(transcription.notes, vec![note(48), note(50)]);
+        transcription.reset_notes();
+        assert_eq!(transcription.notes, vec![note(52)]);
+        assert!(!transcription.edited);
+        assert_eq!(transcription.notes_generation, 2);
+    }
+
+    #[test]
+    fn spectrum_framing_test() {
+        let source = FftSource::default();
+        let mut config = FftConfig {
+            resolution_hz: 10.0,
+            ..Default::default()
+        };
+        let framing = SpectrumFraming::new(&source, &config);
+        assert_eq!(framing.window_size, 4800);
+        assert_eq!(framing.fft_size, 4800);
+        assert_eq!(framing.bin_hz(48000), 10.0);
+
+        config.zero_padding = 4;
+        let framing = SpectrumFraming::new(&source, &config);
+        assert_eq!(framing.fft_size, 19200);
+        assert_eq!(framing.bin_hz(48000), 2.5);
+        // zero padding doesn't change the time resolution
+        assert_eq!(framing.hop_sec(48000), 0.1);
+
+        config.fft_power_of_two = true;
+        assert_eq!(SpectrumFraming::new(&source, &config).fft_size, 32768);
+
+        // the image width is limited
+        config.zero_padding = 8;
+        let framing = SpectrumFraming::new(&source, &config);
+        assert_eq!(framing.fft_size, 65536);
+        assert!(framing.bins(48000) <= MAX_SPECTRUM_WIDTH);
+        config.fft_power_of_two = false;
+        let framing = SpectrumFraming::new(&source, &config);
+        assert!(framing.bins(48000) <= MAX_SPECTRUM_WIDTH);
+
+        // short hops are limited by the image height
+        assert_eq!(framing.rows, 900);
+        for hop in [Hop::Milliseconds(1.0), Hop::Overlap(0.9999)] {
+            for centered in [false, true] {
+                config.hop = hop;
+                config.padding.centered = centered;
+                let framing = SpectrumFraming::new(&source, &config);
+                assert_eq!(framing.window_size - framing.overlapping, 528);
+                assert!(
+                    framing.rows as usize <= MAX_SPECTRUM_ROWS,
+                    "{}",
+                    framing.rows
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fft_frames_padding_test() {
+        let source = FftSource {
+            data: (0..48000)
+                .map(|i| (i as f32 * 0.05).sin() + (i as f32 * 0.0123).cos())
+                .collect(),
+            ..Default::default()
+        };
+        let config = FftConfig {
+            resolution_hz: 10.0,
+            duration_sec: 1,
+            zero_padding: 4,
+            ..Default::default()
+        };
+        let frames = fft_frames(&source, &config);
+        assert_eq!(frames.len(), 10);
+
+        // every frame matches a transform of its own zero padded window
+        let framing = SpectrumFraming::new(&source, &config);
+        let r2c = RealFftPlanner::<f32>::new().plan_fft_forward(framing.fft_size);
+        let window = config.window(framing.window_size);
+        for (frame, chunk) in frames.iter().zip(source.data.chunks(framing.window_size)) {
+            let mut input_buf = r2c.make_input_vec();
+            for ((input, sample), window) in input_buf.iter_mut().zip(chunk).zip(&window) {
+                *input = sample * window;
+            }
+            let mut output_buf = r2c.make_output_vec();
+            r2c.process(&mut input_buf, &mut output_buf).unwrap();
+            for (value, expected) in frame.iter().zip(&output_buf) {
+                assert!((value - expected.norm()).abs() < 1e-2, "{value} {expected}");
+            }
+        }
+    }
+
+    #[test]
+    fn measure_notes_test() {
+        let source = FftSource {
+            data: vec![0.0; 48000],
+            ..Default::default()
+        };
+        let config = FftConfig::default();
+        let note = |start_sec, duration_sec| notes::Note {
+            key: 48,
+            start_sec,
+            duration_sec,
+        };
+        // notes beyond or at the end of the samples, or with no samples at all, are skipped
+        let notes = [note(2.0, 0.5), note(0.9999, 0.5), note(0.5, -0.1)];
+        assert_eq!(measure_notes(&source, &config, &notes), vec![]);
+        assert_eq!(
+            measure_notes(&FftSource::default(), &config, &notes),
+            vec![]
+        );
+    }
+
     #[test]
     fn keyboard_pos_to_key_test() {
         // Outside the keyboard
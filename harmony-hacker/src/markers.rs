@@ -0,0 +1,149 @@
+//! Horizontal markers across the spectrum for note onsets and beats, and the chord lane.
+
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle, window::PrimaryWindow};
+use bevy_egui::{egui, EguiContexts};
+
+use crate::{piano_roll::RollGeometry, FftConfig, MainView, Spectrum, Transcription};
+
+/// Markers are drawn above the spectrum but below the piano roll notes
+const MARKERS_Z: f32 = 1.0;
+/// The width of the chord lane along the left edge of the spectrum
+const CHORD_LANE_WIDTH: f32 = 60.0;
+
+#[derive(Component)]
+pub(crate) struct Marker;
+
+#[derive(Resource)]
+pub(crate) struct Markers {
+    mesh: Handle<Mesh>,
+    onset_material: Handle<ColorMaterial>,
+    beat_material: Handle<ColorMaterial>,
+}
+
+pub(crate) fn setup_markers(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.insert_resource(Markers {
+        // unit rectangle which is scaled to the spectrum width
+        mesh: meshes.add(Rectangle::new(1.0, 1.0)),
+        onset_material: materials.add(Color::rgba(1.0, 0.2, 0.2, 0.7)),
+        beat_material: materials.add(Color::rgba(0.2, 0.5, 1.0, 0.7)),
+    });
+}
+
+/// Respawns markers when the transcription or the visible range changes
+pub(crate) fn update_markers(
+    mut commands: Commands,
+    markers: Res<Markers>,
+    transcription: Res<Transcription>,
+    config: Res<FftConfig>,
+    spectrum: Query<(&Transform, &Sprite), With<Spectrum>>,
+    existing: Query<Entity, With<Marker>>,
+) {
+    if !transcription.is_changed() && !config.is_changed() {
+        return;
+    }
+    let Ok((spectrum_transform, spectrum_sprite)) = spectrum.get_single() else {
+        return;
+    };
+    let geometry = RollGeometry::new(spectrum_transform, spectrum_sprite, &config);
+    let width = spectrum_sprite.custom_size.unwrap_or_default().x;
+
+    for entity in existing.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let onsets = transcription
+        .onsets
+        .iter()
+        .map(|onset| (onset, &markers.onset_material));
+    let beats = transcription
+        .beats
+        .iter()
+        .flat_map(|beats| beats.beats.iter())
+        .map(|beat| (beat, &markers.beat_material));
+    for (time, material) in onsets.chain(beats) {
+        if !geometry.is_visible(*time) {
+            continue;
+        }
+        commands.spawn((
+            MaterialMesh2dBundle {
+                mesh: markers.mesh.clone().into(),
+                material: material.clone(),
+                transform: Transform::from_translation(Vec3::new(
+                    spectrum_transform.translation.x,
+                    geometry.time_to_y(*time),
+                    MARKERS_Z,
+                ))
+                .with_scale(Vec3::new(width, 1.0, 1.0)),
+                ..default()
+            },
+            Marker,
+        ));
+    }
+}
+
+/// Draws recognized chords as a lane of labeled boxes along the left edge of the spectrum
+pub(crate) fn chord_lane_ui(
+    mut contexts: EguiContexts,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    transcription: Res<Transcription>,
+    config: Res<FftConfig>,
+    spectrum: Query<(&Transform, &Sprite), With<Spectrum>>,
+    view: Res<MainView>,
+) {
+    // the score has own chord symbols
+    if *view != MainView::Spectrum {
+        return;
+    }
+    let Ok((spectrum_transform, spectrum_sprite)) = spectrum.get_single() else {
+        return;
+    };
+    let window = windows.single();
+    let geometry = RollGeometry::new(spectrum_transform, spectrum_sprite, &config);
+    let left =
+        spectrum_transform.translation.x - spectrum_sprite.custom_size.unwrap_or_default().x / 2.0;
+    // from the world coordinates with (0,0) in the center to egui's with (0,0) in the top left corner
+    let to_screen =
+        |x: f32, y: f32| egui::Pos2::new(x + window.width() / 2.0, window.height() / 2.0 - y);
+    let flats = transcription
+        .key
+        .is_some_and(|estimate| estimate.key.key_signature() < 0);
+
+    let painter = contexts
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for segment in &transcription.chords {
+        let Some(chord) = segment.chord else {
+            continue;
+        };
+        let note = crate::notes::Note {
+            key: 0,
+            start_sec: segment.start_sec,
+            duration_sec: segment.end_sec - segment.start_sec,
+        };
+        let Some((bottom, top)) = geometry.visible_range(&note) else {
+            continue;
+        };
+
+        let rect = egui::Rect::from_two_pos(
+            to_screen(left, bottom),
+            to_screen(left + CHORD_LANE_WIDTH, top),
+        );
+        painter.rect(
+            rect.shrink(0.5),
+            2.0,
+            egui::Color32::from_black_alpha(160),
+            egui::Stroke::new(1.0, egui::Color32::GRAY),
+        );
+        painter.text(
+            rect.left_bottom() + egui::Vec2::new(3.0, -2.0),
+            egui::Align2::LEFT_BOTTOM,
+            chord.symbol(flats),
+            egui::FontId::proportional(12.0),
+            egui::Color32::WHITE,
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Multi-resolution Goertzel spectrogram.
+//!
+//! A single window length is a compromise: close bass keys need long windows, while short treble
+//! notes are smeared by them. Here the window is halved for every octave above the bass, and all
+//! windows of a frame share its center, so keys of every octave line up on a common time axis.
+
+use crate::goertzel::Goertzel;
+
+/// Octaves that keep the full window, A0-B2
+const FULL_WINDOW_OCTAVES: u32 = 3;
+/// The frequency of A0, octaves are counted from it
+const A0_HZ: f32 = 27.5;
+/// The shortest window in periods of the key frequency. Adjacent keys are 6% apart, so they are a
+/// bin apart with 17 periods, which separates them with the rectangular window, and the twice wider
+/// main lobe of Hann and similar windows needs twice more.
+const MIN_PERIODS: f32 = 34.0;
+
+/// The window length at the frequency for the `base_window` of the bass. Octaves start at C of the
+/// nearest key, so A0-B0 is the octave 0 and C8 is the octave 8.
+pub(crate) fn window_size(hz: f32, base_window: usize, sample_rate: u32) -> usize {
+    let key = (12.0 * (hz / A0_HZ).log2()).round().max(0.0) as u32;
+    let octave = (key + 9) / 12;
+    let window = base_window
+        >> octave
+            .saturating_sub(FULL_WINDOW_OCTAVES - 1)
+            .min(usize::BITS - 1);
+    let min_window = (MIN_PERIODS * sample_rate as f32 / hz).ceil() as usize;
+    window.max(min_window.min(base_window))
+}
+
+/// Goertzel magnitudes at frequencies for each frame center. The window of each frequency is
+/// centered on the frame center and samples out of range are zeros.
+pub(crate) fn frames(
+    samples: &[f32],
+    sample_rate: u32,
+    frequencies: &[f32],
+    base_window: usize,
+    window: impl Fn(usize) -> Vec<f32>,
+    centers: impl Iterator<Item = usize>,
+) -> Vec<Vec<f32>> {
+    let sizes: Vec<usize> = frequencies
+        .iter()
+        .map(|hz| window_size(*hz, base_window, sample_rate))
+        .collect();
+    // frequencies of an octave share the window
+    let mut windows: Vec<(usize, Vec<f32>)> = Vec::new();
+    for &size in &sizes {
+        if windows.iter().all(|(s, _)| *s != size) {
+            windows.push((size, window(size)));
+        }
+    }
+    let mut states: Vec<Goertzel> = frequencies
+        .iter()
+        .map(|frequency| Goertzel::new(sample_rate, *frequency))
+        .collect();
+
+    centers
+        .map(|center| {
+            let mut magnitudes = vec![0.0; frequencies.len()];
+            for ((magnitude, state), &size) in magnitudes.iter_mut().zip(&mut states).zip(&sizes) {
+                let (_, window) = windows.iter().find(|(s, _)| *s == size).unwrap();
+                let start = center as isize - (size / 2) as isize;
+                for (i, w) in window.iter().enumerate() {
+                    let sample = usize::try_from(start + i as isize)
+                        .ok()
+                        .and_then(|i| samples.get(i))
+                        .copied()
+                        .unwrap_or(0.0);
+                    state.process(sample * w);
+                }
+                *magnitude = state.magnitude(size as u32);
+                state.reset();
+            }
+            magnitudes
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notes::KEYS_COUNT;
+    use pretty_assertions::assert_eq;
+    use std::f32::consts::PI;
+
+    fn key_hz(key: u8) -> f32 {
+        440.0 * 2.0f32.powf((key as f32 - 48.0) / 12.0)
+    }
+
+    #[test]
+    fn window_size_test() {
+        let sizes: Vec<usize> = [0, 2, 3, 26, 27, 39, 51, 63, 75, 87]
+            .into_iter()
+            .map(|key| window_size(key_hz(key), 48000, 48000))
+            .collect();
+        // A0, B0, C1, B2 keep the window, C3-C8 halve it per octave down to 34 periods
+        assert_eq!(
+            sizes,
+            vec![48000, 48000, 48000, 48000, 24000, 12000, 6000, 3000, 1500, 750]
+        );
+        assert_eq!(window_size(key_hz(87), 4800, 48000), 390);
+        // the minimum is never longer than the base window
+        assert_eq!(window_size(key_hz(0), 100, 48000), 100);
+        // partials between keys go by the nearest key
+        assert_eq!(window_size(key_hz(27) * 0.98, 48000, 48000), 24000);
+    }
+
+    #[test]
+    fn key_frames_test() {
+        let sample_rate = 8000;
+        let frequencies: Vec<f32> = (0..KEYS_COUNT as u8).map(key_hz).collect();
+        // a long A2 and a short A6 burst in the middle
+        let a6 = 60;
+        let samples: Vec<f32> = (0..16000)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                let burst = if (7900..8100).contains(&i) { 1.0 } else { 0.0 };
+                (2.0 * PI * key_hz(24) * t).sin() + burst * (2.0 * PI * key_hz(a6) * t).sin()
+            })
+            .collect();
+
+        let hop = 100;
+        let frames = frames(
+            &samples,
+            sample_rate,
+            &frequencies,
+            4000,
+            |size| vec![1.0; size],
+            (0..160).map(|frame| frame * hop),
+        );
+        assert_eq!(frames.len(), 160);
+
+        // A2 is separated from its neighbours in the middle of the range
+        let middle = &frames[80];
+        assert!(middle[24] > 0.95, "{}", middle[24]);
+        assert!(middle[23] < 0.1 && middle[25] < 0.1, "{middle:?}");
+
+        // A6 is only in frames whose 500 samples window covers most of the burst, while the full
+        // window would smear it over 40 frames
+        let a6_frames: Vec<usize> = (0..frames.len())
+            .filter(|&frame| frames[frame][a6 as usize] > 0.2)
+            .collect();
+        assert_eq!(a6_frames, vec![78, 79, 80, 81, 82]);
+    }
+
+    #[test]
+    fn treble_separation_test() {
+        let sample_rate = 48000;
+        let frequencies: Vec<f32> = (0..KEYS_COUNT as u8).map(key_hz).collect();
+        // C8 at 10 Hz resolution gets the shortest window
+        let c8 = 87;
+        let samples: Vec<f32> = (0..4800)
+            .map(|i| (2.0 * PI * key_hz(c8) * i as f32 / sample_rate as f32).sin())
+            .collect();
+
+        let rectangular: fn(usize) -> Vec<f32> = |size| vec![1.0; size];
+        for window in [rectangular, crate::window_fn::hann] {
+            let frames = frames(
+                &samples,
+                sample_rate,
+                &frequencies,
+                4800,
+                window,
+                [2400].into_iter(),
+            );
+            let [.., a_sharp7, b7, c8] = frames[0][..] else {
+                unreachable!()
+            };
+            assert!(b7 < 0.1 * c8 && a_sharp7 < 0.1 * c8, "{a_sharp7} {b7} {c8}");
+        }
+    }
+}
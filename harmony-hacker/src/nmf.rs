@@ -0,0 +1,355 @@
+//! Polyphonic decomposition with the non-negative matrix factorisation of the magnitude spectrogram.
+//! https://en.wikipedia.org/wiki/Non-negative_matrix_factorization
+//!
+//! The spectrogram `V` (bins x frames) is approximated by `W H`, where columns of `W` are spectral
+//! templates of 88 keys and rows of `H` are their activations over time. Templates are either built
+//! from the harmonic series of each key or learned from a recording of single notes of the same piano.
+//! The multiplicative updates keep zeros of templates, so harmonic templates stay harmonic.
+
+use bevy::prelude::Resource;
+
+use crate::{
+    inharmonicity::Inharmonicity,
+    notes::{Note, KEYS_COUNT},
+};
+
+/// The number of partials in harmonic templates
+const PARTIALS: u32 = 12;
+/// The half-width of a partial's peak in bins, the main lobe of the Hann window
+const PEAK_HALF_WIDTH: f32 = 2.0;
+/// Bins of learned templates below this ratio to the peak are dropped to keep templates sparse
+const MIN_TEMPLATE_RATIO: f32 = 0.01;
+/// Multiplicative update iterations
+const ITERATIONS: usize = 30;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum TemplateSource {
+    /// Peaks at inharmonic partials of each key
+    #[default]
+    Harmonic,
+    /// Spectra of single notes learned from a recording, harmonic for keys that weren't played
+    Learned,
+}
+
+/// Spectral templates of keys, sparse columns of `W` as pairs of the bin and its magnitude.
+/// Each template is normalized to the unit peak.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Templates {
+    pub(crate) bin_hz: f32,
+    pub(crate) bins: usize,
+    columns: Vec<Vec<(usize, f32)>>,
+}
+
+/// Templates learned from the recording of single notes, if any
+#[derive(Default, Resource)]
+pub(crate) struct LearnedTemplates(pub(crate) Option<Templates>);
+
+impl Templates {
+    /// Templates with peaks at partials of keys, the amplitude of the partial `n` is `1/n`
+    pub(crate) fn harmonic(
+        bins: usize,
+        bin_hz: f32,
+        key_frequency: impl Fn(u8) -> f32,
+        inharmonicity: &Inharmonicity,
+    ) -> Self {
+        let columns = (0..KEYS_COUNT as u8)
+            .map(|key| {
+                let mut column = vec![0.0; bins];
+                for n in 1..=PARTIALS {
+                    let center = inharmonicity.partial(key, key_frequency(key), n) / bin_hz;
+                    let from = (center - PEAK_HALF_WIDTH).ceil().max(0.0) as usize;
+                    let to =
+                        ((center + PEAK_HALF_WIDTH).floor() as usize).min(bins.saturating_sub(1));
+                    for (bin, value) in column.iter_mut().enumerate().take(to + 1).skip(from) {
+                        let distance = (bin as f32 - center) / PEAK_HALF_WIDTH;
+                        let lobe = (distance * std::f32::consts::FRAC_PI_2).cos().powi(2);
+                        *value = f32::max(*value, lobe / n as f32);
+                    }
+                }
+                sparse_column(&column)
+            })
+            .collect();
+        Self {
+            bin_hz,
+            bins,
+            columns,
+        }
+    }
+
+    /// Averages spectra of frames where a single note sounds. Keys that weren't played alone keep
+    /// `fallback` templates, which must have the same bins.
+    pub(crate) fn learn(
+        frames: &[Vec<f32>],
+        start_sec: f32,
+        hop_sec: f32,
+        notes: &[Note],
+        fallback: Templates,
+    ) -> Self {
+        let frame_of = |time_sec: f32| ((time_sec - start_sec) / hop_sec).round().max(0.0) as usize;
+        let mut sums = vec![vec![0.0; fallback.bins]; KEYS_COUNT];
+        for (i, note) in notes.iter().enumerate() {
+            let end_sec = note.start_sec + note.duration_sec;
+            let overlaps = notes.iter().enumerate().any(|(j, other)| {
+                i != j
+                    && other.start_sec < end_sec
+                    && note.start_sec < other.start_sec + other.duration_sec
+            });
+            if overlaps {
+                continue;
+            }
+            let frames = frames
+                .get(frame_of(note.start_sec)..frame_of(end_sec).min(frames.len()))
+                .unwrap_or_default();
+            for frame in frames {
+                for (sum, magnitude) in sums[note.key as usize].iter_mut().zip(frame) {
+                    *sum += magnitude;
+                }
+            }
+        }
+
+        let columns = sums
+            .into_iter()
+            .zip(fallback.columns)
+            .map(|(sum, fallback)| {
+                if sum.iter().any(|value| *value > 0.0) {
+                    sparse_column(&sum)
+                } else {
+                    fallback
+                }
+            })
+            .collect();
+        Self {
+            bin_hz: fallback.bin_hz,
+            bins: fallback.bins,
+            columns,
+        }
+    }
+
+    /// Linearly interpolates templates to other bins, e.g. after the resolution changes
+    pub(crate) fn resample(&self, bins: usize, bin_hz: f32) -> Self {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| {
+                let mut dense = vec![0.0; self.bins];
+                for (bin, value) in column {
+                    dense[*bin] = *value;
+                }
+                let resampled: Vec<f32> = (0..bins)
+                    .map(|bin| {
+                        let position = bin as f32 * bin_hz / self.bin_hz;
+                        let (index, fraction) = (position.floor() as usize, position.fract());
+                        let at = |index: usize| dense.get(index).copied().unwrap_or_default();
+                        at(index) * (1.0 - fraction) + at(index + 1) * fraction
+                    })
+                    .collect();
+                sparse_column(&resampled)
+            })
+            .collect();
+        Self {
+            bin_hz,
+            bins,
+            columns,
+        }
+    }
+}
+
+/// Keeps significant bins of the template normalized to the unit peak
+fn sparse_column(column: &[f32]) -> Vec<(usize, f32)> {
+    let max = column.iter().copied().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return vec![];
+    }
+    column
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value >= max * MIN_TEMPLATE_RATIO)
+        .map(|(bin, value)| (bin, value / max))
+        .collect()
+}
+
+/// Activations of keys in each frame of magnitude spectra with the same bins as templates.
+/// Minimizes the Kullback-Leibler divergence between `V` and `W H` with multiplicative updates,
+/// templates are updated as well if `update_templates` is set.
+/// https://papers.nips.cc/paper/1861-algorithms-for-non-negative-matrix-factorization
+pub(crate) fn decompose(
+    frames: &[Vec<f32>],
+    templates: &Templates,
+    update_templates: bool,
+) -> Vec<[f32; KEYS_COUNT]> {
+    let mut columns = templates.columns.clone();
+    let mean = frames.iter().flatten().sum::<f32>() / frames.iter().flatten().count().max(1) as f32;
+    let mut activations = vec![[mean; KEYS_COUNT]; frames.len()];
+
+    let mut ratios: Vec<Vec<f32>> = frames.iter().map(|frame| vec![0.0; frame.len()]).collect();
+    for iteration in 0..ITERATIONS {
+        update_ratios(frames, &columns, &activations, &mut ratios);
+        for (frame_activations, ratios) in activations.iter_mut().zip(&ratios) {
+            for (activation, column) in frame_activations.iter_mut().zip(&columns) {
+                let (numerator, denominator) = column
+                    .iter()
+                    .filter(|(bin, _)| *bin < ratios.len())
+                    .fold((0.0, 0.0), |(numerator, denominator), (bin, value)| {
+                        (numerator + value * ratios[*bin], denominator + value)
+                    });
+                if denominator > 0.0 {
+                    *activation *= numerator / denominator;
+                }
+            }
+        }
+
+        // templates adapt only after activations settle, otherwise they absorb octaves of each other
+        if !update_templates || iteration < ITERATIONS / 2 {
+            continue;
+        }
+        update_ratios(frames, &columns, &activations, &mut ratios);
+        for (key, column) in columns.iter_mut().enumerate() {
+            let total: f32 = activations.iter().map(|frame| frame[key]).sum();
+            if total <= 0.0 {
+                continue;
+            }
+            for (bin, value) in column.iter_mut() {
+                let numerator: f32 = activations
+                    .iter()
+                    .zip(&ratios)
+                    .map(|(frame, ratios)| {
+                        frame[key] * ratios.get(*bin).copied().unwrap_or_default()
+                    })
+                    .sum();
+                *value *= numerator / total;
+            }
+            // keep templates at the unit peak, so activations stay comparable to magnitudes
+            let max = column.iter().map(|(_, value)| *value).fold(0.0, f32::max);
+            if max > 0.0 {
+                column.iter_mut().for_each(|(_, value)| *value /= max);
+                activations.iter_mut().for_each(|frame| frame[key] *= max);
+            }
+        }
+    }
+    activations
+}
+
+/// Ratios `V / (W H)` of the current approximation
+fn update_ratios(
+    frames: &[Vec<f32>],
+    columns: &[Vec<(usize, f32)>],
+    activations: &[[f32; KEYS_COUNT]],
+    ratios: &mut [Vec<f32>],
+) {
+    for ((frame, frame_activations), ratios) in frames.iter().zip(activations).zip(ratios) {
+        ratios.fill(0.0);
+        for (column, activation) in columns.iter().zip(frame_activations) {
+            for (bin, value) in column {
+                if let Some(approximation) = ratios.get_mut(*bin) {
+                    *approximation += value * activation;
+                }
+            }
+        }
+        for (ratio, magnitude) in ratios.iter_mut().zip(frame) {
+            *ratio = magnitude / (*ratio + f32::EPSILON);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn equal(key: u8) -> f32 {
+        440.0 * 2.0f32.powf((key as f32 - 48.0) / 12.0)
+    }
+
+    /// The dense spectrum of keys sounding with given amplitudes
+    fn mix(templates: &Templates, keys: &[(usize, f32)]) -> Vec<f32> {
+        let mut frame = vec![0.0; templates.bins];
+        for (key, amplitude) in keys {
+            for (bin, value) in &templates.columns[*key] {
+                frame[*bin] += value * amplitude;
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn harmonic_templates_test() {
+        let templates = Templates::harmonic(500, 10.0, equal, &Inharmonicity::default());
+        // A4 peaks at 440 Hz, the octave is weaker and slightly sharp
+        let a4 = &templates.columns[48];
+        assert!(a4.contains(&(44, 1.0)));
+        let octave = a4.iter().find(|(bin, _)| *bin == 88).unwrap().1;
+        assert!(octave > 0.45 && octave < 0.5, "{octave}");
+        // partials above the highest bin are dropped
+        assert!(templates.columns[87].iter().all(|(bin, _)| *bin < 500));
+
+        let resampled = templates.resample(1000, 5.0);
+        assert!(resampled.columns[48].contains(&(88, 1.0)));
+    }
+
+    #[test]
+    fn decompose_test() {
+        let templates = Templates::harmonic(400, 5.0, equal, &Inharmonicity::default());
+        // C4 alone, then C major chord, then E4 with G4
+        let expected = [
+            vec![(39, 1.0)],
+            vec![(39, 0.5), (43, 0.8), (46, 0.3)],
+            vec![(43, 0.6), (46, 0.6)],
+        ];
+        let frames: Vec<Vec<f32>> = expected.iter().map(|keys| mix(&templates, keys)).collect();
+
+        // updated templates may share the energy slightly differently
+        for (update_templates, tolerance) in [(false, 0.05), (true, 0.2)] {
+            let activations = decompose(&frames, &templates, update_templates);
+            for (frame, keys) in activations.iter().zip(&expected) {
+                for (key, activation) in frame.iter().enumerate() {
+                    let expected = keys
+                        .iter()
+                        .find(|(expected, _)| *expected == key)
+                        .map_or(0.0, |(_, amplitude)| *amplitude);
+                    assert!(
+                        (activation - expected).abs() < tolerance,
+                        "{update_templates} {key} {activation} vs {expected}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn learn_templates_test() {
+        let harmonic = Templates::harmonic(400, 5.0, equal, &Inharmonicity::default());
+        // A4 with an unusually strong 3rd partial sounds in frames 1..3, then with C4 in frames 4..5
+        let mut a4 = vec![0.0; 400];
+        a4[88] = 2.0;
+        a4[176] = 1.0;
+        a4[264] = 3.0;
+        let frames = vec![
+            vec![0.0; 400],
+            a4.clone(),
+            a4.clone(),
+            vec![0.0; 400],
+            a4.clone(),
+        ];
+        let note = |key, start_sec, duration_sec| Note {
+            key,
+            start_sec,
+            duration_sec,
+        };
+        let learned = Templates::learn(
+            &frames,
+            10.0,
+            0.5,
+            &[
+                note(48, 10.5, 1.0),
+                note(48, 12.0, 0.5),
+                note(39, 12.0, 0.5),
+            ],
+            harmonic.clone(),
+        );
+        assert_eq!(
+            learned.columns[48],
+            vec![(88, 2.0 / 3.0), (176, 1.0 / 3.0), (264, 1.0)]
+        );
+        // C4 was played only together with A4
+        assert_eq!(learned.columns[39], harmonic.columns[39]);
+    }
+}
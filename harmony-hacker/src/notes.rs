@@ -0,0 +1,142 @@
+//! Note extraction from per-key activations.
+
+/// Number of keys on the piano keyboard
+pub(crate) const KEYS_COUNT: usize = 88;
+
+/// A single note played on the piano
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Note {
+    /// Key number 0..88, where 0 is A0 and 87 is C8
+    pub(crate) key: u8,
+    /// Start time in seconds from the beginning of the source
+    pub(crate) start_sec: f32,
+    /// Duration in seconds
+    pub(crate) duration_sec: f32,
+}
+
+/// Extracts notes from per-frame key activations (e.g. Goertzel magnitudes).
+///
+/// A note starts when the activation of a key rises above `threshold` and lasts until it falls below
+/// the half of it. A sounding note is split into two if its activation grows at one of `onset_frames`
+/// (sorted frame indices), which means that the key was struck again.
+/// Notes shorter than two frames are considered to be noise and dropped.
+/// Resulting notes are sorted by the start time and then by the key.
+pub(crate) fn transcribe(
+    frames: &[[f32; KEYS_COUNT]],
+    onset_frames: &[usize],
+    start_sec: f32,
+    frame_duration_sec: f32,
+    threshold: f32,
+) -> Vec<Note> {
+    const MIN_FRAMES: usize = 2;
+
+    let mut notes = Vec::new();
+    // the frame where the currently sounding note started
+    let mut note_on = [None::<usize>; KEYS_COUNT];
+
+    let finish_note = |notes: &mut Vec<Note>, key: usize, from: usize, to: usize| {
+        if to - from >= MIN_FRAMES {
+            notes.push(Note {
+                key: key as u8,
+                start_sec: start_sec + from as f32 * frame_duration_sec,
+                duration_sec: (to - from) as f32 * frame_duration_sec,
+            });
+        }
+    };
+
+    let mut onset_frames = onset_frames.iter().peekable();
+    for (frame_idx, frame) in frames.iter().enumerate() {
+        let is_onset = onset_frames.next_if_eq(&&frame_idx).is_some();
+        for (key, &activation) in frame.iter().enumerate() {
+            match note_on[key] {
+                None if activation >= threshold => note_on[key] = Some(frame_idx),
+                Some(from) if is_onset && activation > frames[frame_idx - 1][key] => {
+                    finish_note(&mut notes, key, from, frame_idx);
+                    note_on[key] = Some(frame_idx);
+                }
+                Some(from) if activation < threshold / 2.0 => {
+                    finish_note(&mut notes, key, from, frame_idx);
+                    note_on[key] = None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Close notes that are still sounding at the end
+    for (key, from) in note_on.iter().enumerate() {
+        if let Some(from) = from {
+            finish_note(&mut notes, key, *from, frames.len());
+        }
+    }
+
+    notes.sort_by(|a, b| a.start_sec.total_cmp(&b.start_sec).then(a.key.cmp(&b.key)));
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn transcribe_test() {
+        // silence
+        let frames = vec![[0.0; KEYS_COUNT]; 10];
+        assert_eq!(transcribe(&frames, &[], 0.0, 0.1, 0.5), vec![]);
+
+        let mut frames = vec![[0.0; KEYS_COUNT]; 10];
+        // A4 from the 1st to the 5th frame, decaying below the threshold but above the half of it
+        frames[1][48] = 1.0;
+        frames[2][48] = 0.8;
+        frames[3][48] = 0.4;
+        frames[4][48] = 0.3;
+        // C4 is too short
+        frames[2][39] = 1.0;
+        // C8 sounds until the end
+        frames[7][87] = 0.6;
+        frames[8][87] = 0.6;
+        frames[9][87] = 0.6;
+
+        let notes = transcribe(&frames, &[0, 3, 8], 10.0, 0.5, 0.5);
+        assert_eq!(
+            notes,
+            vec![
+                Note {
+                    key: 48,
+                    start_sec: 10.5,
+                    duration_sec: 2.0,
+                },
+                Note {
+                    key: 87,
+                    start_sec: 13.5,
+                    duration_sec: 1.5,
+                },
+            ]
+        );
+
+        // C4 struck twice without releasing the key
+        let mut frames = vec![[0.0; KEYS_COUNT]; 8];
+        for (frame, activation) in frames.iter_mut().zip([1.0, 0.8, 0.6, 1.0, 0.8, 0.6, 0.4]) {
+            frame[39] = activation;
+        }
+        let note = |start_sec, duration_sec| Note {
+            key: 39,
+            start_sec,
+            duration_sec,
+        };
+        assert_eq!(
+            transcribe(&frames, &[], 0.0, 1.0, 0.5),
+            vec![note(0.0, 7.0)]
+        );
+        assert_eq!(
+            transcribe(&frames, &[0, 3], 0.0, 1.0, 0.5),
+            vec![note(0.0, 3.0), note(3.0, 4.0)]
+        );
+        // onset without the activation growth
+        assert_eq!(
+            transcribe(&frames, &[4], 0.0, 1.0, 0.5),
+            vec![note(0.0, 7.0)]
+        );
+    }
+}
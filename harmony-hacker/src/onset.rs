@@ -0,0 +1,238 @@
+//! Note onset detection.
+//! https://www.eecs.qmul.ac.uk/~josh/documents/2005/BelloEtAl-IEEE-TSALP-2005.pdf
+//!
+//! The signal is split into overlapping frames, each frame is transformed into a spectrum and
+//! a detection function (one value per frame) is calculated from adjacent spectra.
+//! Onsets are peaks of the detection function above an adaptive (moving median) threshold.
+
+use std::io::Write;
+
+use realfft::num_complex::Complex;
+
+use crate::stft;
+
+/// The duration of the analysis window, a good trade-off between time and frequency resolution for onsets
+const WINDOW_SEC: f32 = 0.046;
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub(crate) enum OnsetMethod {
+    /// Sum of positive magnitude differences between adjacent spectra
+    #[default]
+    SpectralFlux,
+    /// Energy weighted by the bin frequency, emphasizing percussive attacks
+    HighFrequencyContent,
+    /// Distance between the spectrum and its prediction from two previous frames,
+    /// sensitive to both magnitude and phase changes
+    ComplexDomain,
+}
+
+/// Parameters of the adaptive peak picking
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PeakPicking {
+    /// Number of frames before and after the current one used for the moving median and local maximum
+    pub(crate) window: usize,
+    /// Fixed part of the threshold, in units of the normalized detection function
+    pub(crate) delta: f32,
+    /// Weight of the moving median in the threshold
+    pub(crate) lambda: f32,
+    /// Minimal distance between two onsets in frames
+    pub(crate) min_distance: usize,
+}
+
+impl Default for PeakPicking {
+    fn default() -> Self {
+        Self {
+            window: 5,
+            delta: 0.1,
+            lambda: 1.0,
+            min_distance: 3,
+        }
+    }
+}
+
+/// Calculates the onset detection function for the sequence of spectra, one value per spectrum.
+pub(crate) fn detection_function(spectra: &[Vec<Complex<f32>>], method: OnsetMethod) -> Vec<f32> {
+    let zero = Complex::new(0.0, 0.0);
+    spectra
+        .iter()
+        .enumerate()
+        .map(|(i, spectrum)| {
+            let prev = i.checked_sub(1).map(|i| &spectra[i]);
+            let prev_prev = i.checked_sub(2).map(|i| &spectra[i]);
+            match method {
+                OnsetMethod::SpectralFlux => spectrum
+                    .iter()
+                    .enumerate()
+                    .map(|(k, bin)| {
+                        let prev = prev.map_or(0.0, |prev| prev[k].norm());
+                        (bin.norm() - prev).max(0.0)
+                    })
+                    .sum(),
+                OnsetMethod::HighFrequencyContent => spectrum
+                    .iter()
+                    .enumerate()
+                    .map(|(k, bin)| k as f32 * bin.norm_sqr())
+                    .sum(),
+                OnsetMethod::ComplexDomain => spectrum
+                    .iter()
+                    .enumerate()
+                    .map(|(k, bin)| {
+                        let prev = prev.map_or(zero, |prev| prev[k]);
+                        let prev_prev = prev_prev.map_or(zero, |prev_prev| prev_prev[k]);
+                        // the same magnitude and the phase advanced by the same amount as before
+                        let phase = 2.0 * prev.arg() - prev_prev.arg();
+                        let predicted = Complex::from_polar(prev.norm(), phase);
+                        (bin - predicted).norm()
+                    })
+                    .sum(),
+            }
+        })
+        .collect()
+}
+
+/// Finds onsets as local maximums of the detection function above the adaptive threshold.
+/// Returns indices of the frames with onsets.
+pub(crate) fn pick_peaks(detection: &[f32], params: &PeakPicking) -> Vec<usize> {
+    // Normalize to zero mean and unit max deviation so the threshold doesn't depend on the method
+    let mean = detection.iter().sum::<f32>() / detection.len().max(1) as f32;
+    let max = detection
+        .iter()
+        .map(|value| (value - mean).abs())
+        .fold(0.0, f32::max);
+    if max == 0.0 {
+        return vec![];
+    }
+    let normalized: Vec<f32> = detection.iter().map(|v| (v - mean) / max).collect();
+
+    let mut onsets: Vec<usize> = Vec::new();
+    let mut neighbourhood = Vec::with_capacity(2 * params.window + 1);
+    for (i, &value) in normalized.iter().enumerate() {
+        let from = i.saturating_sub(params.window);
+        let to = (i + params.window + 1).min(normalized.len());
+
+        neighbourhood.clear();
+        neighbourhood.extend_from_slice(&normalized[from..to]);
+        let is_local_max = neighbourhood.iter().all(|v| *v <= value);
+        neighbourhood.sort_by(f32::total_cmp);
+        let median = neighbourhood[neighbourhood.len() / 2];
+
+        let too_close = onsets
+            .last()
+            .is_some_and(|last| i - last < params.min_distance);
+        if is_local_max && value > params.delta + params.lambda * median && !too_close {
+            onsets.push(i);
+        }
+    }
+    onsets
+}
+
+/// The detection function of the signal sampled every `hop_sec` seconds
+pub(crate) struct OnsetStrength {
+    pub(crate) values: Vec<f32>,
+    pub(crate) hop_sec: f32,
+}
+
+/// Calculates the detection function of the signal
+pub(crate) fn onset_strength(
+    samples: &[f32],
+    sample_rate: u32,
+    method: OnsetMethod,
+) -> OnsetStrength {
+    let window_size = onset_window_size(sample_rate);
+    let hop = window_size / 4;
+    let spectra = stft::stft(samples, window_size, hop);
+    OnsetStrength {
+        values: detection_function(&spectra, method),
+        hop_sec: hop as f32 / sample_rate as f32,
+    }
+}
+
+/// Detects onsets and returns their timestamps in seconds from the beginning of the signal
+pub(crate) fn detect_onsets(strength: &OnsetStrength, params: &PeakPicking) -> Vec<f32> {
+    pick_peaks(&strength.values, params)
+        .into_iter()
+        .map(|frame| frame as f32 * strength.hop_sec)
+        .collect()
+}
+
+/// The power of two window size closest to [`WINDOW_SEC`]
+fn onset_window_size(sample_rate: u32) -> usize {
+    let size = (sample_rate as f32 * WINDOW_SEC) as usize;
+    size.next_power_of_two()
+}
+
+/// Writes timestamps in seconds one per line
+pub(crate) fn write_timestamps(mut writer: impl Write, timestamps: &[f32]) -> std::io::Result<()> {
+    for timestamp in timestamps {
+        writeln!(writer, "{timestamp:.3}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// Silence with short sine bursts (decaying notes) at given times
+    fn bursts(sample_rate: u32, duration_sec: f32, onsets_sec: &[f32]) -> Vec<f32> {
+        let mut samples = vec![0.0; (sample_rate as f32 * duration_sec) as usize];
+        for onset in onsets_sec {
+            let start = (onset * sample_rate as f32) as usize;
+            for (i, sample) in samples[start..].iter_mut().enumerate() {
+                let t = i as f32 / sample_rate as f32;
+                *sample += (2.0 * std::f32::consts::PI * 440.0 * t).sin() * (-t * 8.0).exp();
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn pick_peaks_test() {
+        assert_eq!(pick_peaks(&[], &PeakPicking::default()), vec![]);
+        assert_eq!(pick_peaks(&[1.0; 10], &PeakPicking::default()), vec![]);
+
+        let mut detection = vec![0.0; 40];
+        detection[5] = 1.0;
+        detection[6] = 0.5;
+        detection[20] = 0.8;
+        // too close to the previous one
+        detection[21] = 0.8;
+        // too small
+        detection[30] = 0.05;
+        assert_eq!(pick_peaks(&detection, &PeakPicking::default()), vec![5, 20]);
+    }
+
+    #[test]
+    fn detect_onsets_test() {
+        let sample_rate = 16000;
+        let expected = [0.25, 0.8, 1.5];
+        let samples = bursts(sample_rate, 2.0, &expected);
+        let hop_sec = (onset_window_size(sample_rate) / 4) as f32 / sample_rate as f32;
+
+        for method in [
+            OnsetMethod::SpectralFlux,
+            OnsetMethod::HighFrequencyContent,
+            OnsetMethod::ComplexDomain,
+        ] {
+            let strength = onset_strength(&samples, sample_rate, method);
+            let onsets = detect_onsets(&strength, &PeakPicking::default());
+            assert_eq!(onsets.len(), expected.len(), "{method:?}: {onsets:?}");
+            for (onset, expected) in onsets.iter().zip(expected) {
+                // the frame containing the onset might start up to a window earlier
+                let window_sec = onset_window_size(sample_rate) as f32 / sample_rate as f32;
+                assert!(
+                    expected - window_sec <= *onset && *onset <= expected + hop_sec,
+                    "{method:?}: {onset} vs {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn write_timestamps_test() {
+        let mut output = Vec::new();
+        write_timestamps(&mut output, &[0.0, 1.5, 12.3456]).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "0.000\n1.500\n12.346\n");
+    }
+}
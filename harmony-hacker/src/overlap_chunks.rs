@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 /// An iterator over a slice in overlapping (by `overlap` elements) chunks
 /// (`chunk_size` elements at a time).
 ///
@@ -52,22 +54,139 @@ impl<'a, T> Iterator for OverlapChunks<'a, T> {
 
     #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
-        if self.v.is_empty() {
-            (0, Some(0))
-        } else {
-            let overlapped = self.v.len().saturating_sub(self.chunk_size);
-            let full_chunks = overlapped / (self.next_chunk_offset);
-            let remainder = overlapped % (self.next_chunk_offset);
-            if remainder == 0 {
-                (full_chunks + 1, Some(full_chunks + 1))
-            } else {
-                (full_chunks + 2, Some(full_chunks + 2))
+        let count = chunks_count(
+            self.v.len(),
+            self.chunk_size,
+            self.chunk_size - self.next_chunk_offset,
+        );
+        (count, Some(count))
+    }
+}
+
+/// The number of chunks [`overlap_chunks`] yields for a slice of `len` elements,
+/// including the last incomplete one.
+///
+/// [`overlap_chunks`]: OverlapChunksExt::overlap_chunks
+pub(crate) fn chunks_count(len: usize, chunk_size: usize, overlap: usize) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    let overlapped = len.saturating_sub(chunk_size);
+    1 + overlapped.div_ceil(chunk_size - overlap)
+}
+
+/// How chunks that reach beyond the slice are filled
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Boundary {
+    /// Elements beyond the slice are zeros
+    #[default]
+    Zero,
+    /// The slice is mirrored around its first and last elements without repeating them, `abc`
+    /// continues as `ba` and is preceded by `cb`
+    Reflect,
+    /// The first and the last elements are repeated
+    Edge,
+    /// Chunks that don't fit into the slice are dropped, except the zero padded start of
+    /// centered chunks
+    Drop,
+}
+
+/// Boundary handling of [`padded_chunks`]
+///
+/// Chunk `i` starts at `i * (chunk_size - overlap)` or, if centered, is centered there, so
+/// the first chunk is centered at the first element.
+///
+/// [`padded_chunks`]: OverlapChunksExt::padded_chunks
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Padding {
+    pub(crate) boundary: Boundary,
+    pub(crate) centered: bool,
+}
+
+impl Padding {
+    /// The number of chunks for a slice of `len` elements. Unless dropped, chunks continue until
+    /// all elements are covered by a chunk start (or a center if centered).
+    pub(crate) fn chunks_count(&self, len: usize, chunk_size: usize, overlap: usize) -> usize {
+        let hop = chunk_size - overlap;
+        let offset = if self.centered { chunk_size / 2 } else { 0 };
+        match self.boundary {
+            Boundary::Drop if len + offset < chunk_size => 0,
+            Boundary::Drop => (len + offset - chunk_size) / hop + 1,
+            _ if self.centered => len.div_ceil(hop),
+            _ => chunks_count(len, chunk_size, overlap),
+        }
+    }
+}
+
+/// An iterator over a slice in overlapping chunks of exactly `chunk_size` elements, padded at
+/// the boundaries of the slice. Chunks within the slice are borrowed, padded ones are owned.
+///
+/// This struct is created by the [`padded_chunks`] method on [OverlapChunksExt].
+///
+/// [`padded_chunks`]: OverlapChunksExt::padded_chunks
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+#[derive(Clone)]
+pub(crate) struct PaddedChunks<'a, T: 'a> {
+    v: &'a [T],
+    chunk_size: usize,
+    hop: usize,
+    boundary: Boundary,
+    /// The position of the next chunk start, negative for centered chunks at the beginning
+    next_start: isize,
+    remaining: usize,
+}
+
+impl<T: Copy + Default> PaddedChunks<'_, T> {
+    /// The element at the position relative to the slice start, padded beyond the slice
+    fn element(&self, pos: isize) -> T {
+        let len = self.v.len() as isize;
+        if (0..len).contains(&pos) {
+            return self.v[pos as usize];
+        }
+        match self.boundary {
+            Boundary::Zero | Boundary::Drop => T::default(),
+            Boundary::Edge => self.v[pos.clamp(0, len - 1) as usize],
+            Boundary::Reflect if len == 1 => self.v[0],
+            Boundary::Reflect => {
+                // the reflection repeats with the period of 2 * (len - 1)
+                let period = 2 * (len - 1);
+                let pos = pos.rem_euclid(period);
+                self.v[pos.min(period - pos) as usize]
             }
         }
     }
 }
 
-/// Extension trait for slices, which adds the [`overlap_chunks`] method.
+impl<'a, T: Copy + Default> Iterator for PaddedChunks<'a, T> {
+    type Item = Cow<'a, [T]>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 || self.v.is_empty() {
+            return None;
+        }
+        self.remaining -= 1;
+        let start = self.next_start;
+        self.next_start += self.hop as isize;
+
+        let end = start + self.chunk_size as isize;
+        if start >= 0 && end <= self.v.len() as isize {
+            Some(Cow::Borrowed(&self.v[start as usize..end as usize]))
+        } else {
+            Some(Cow::Owned(
+                (start..end).map(|pos| self.element(pos)).collect(),
+            ))
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.v.is_empty() { 0 } else { self.remaining };
+        (remaining, Some(remaining))
+    }
+}
+
+/// Extension trait for slices, which adds the [`overlap_chunks`] and [`padded_chunks`] methods.
 pub(crate) trait OverlapChunksExt<T> {
     /// Returns an iterator over `chunk_size` elements of the slice at a time,
     /// starting at the beginning of the slice similar to [`chunks`], but each
@@ -92,6 +211,33 @@ pub(crate) trait OverlapChunksExt<T> {
     /// [`chunks`]: slice::chunks
     /// [`windows`]: slice::windows
     fn overlap_chunks(&self, chunk_size: usize, overlap: usize) -> OverlapChunks<'_, T>;
+
+    /// Returns an iterator over overlapping chunks like [`overlap_chunks`], but all chunks have
+    /// exactly `chunk_size` elements, padded as configured by `padding`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `overlap` is greater than or equal to `chunk_size`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let slice = [1, 2, 3, 4, 5];
+    /// let padding = Padding { boundary: Boundary::Reflect, centered: false };
+    /// let mut iter = slice.padded_chunks(3, 1, padding);
+    /// assert_eq!(&*iter.next().unwrap(), &[1, 2, 3]);
+    /// assert_eq!(&*iter.next().unwrap(), &[3, 4, 5]);
+    /// assert!(iter.next().is_none());
+    /// ```
+    /// [`overlap_chunks`]: OverlapChunksExt::overlap_chunks
+    fn padded_chunks(
+        &self,
+        chunk_size: usize,
+        overlap: usize,
+        padding: Padding,
+    ) -> PaddedChunks<'_, T>
+    where
+        T: Copy + Default;
 }
 
 impl<T> OverlapChunksExt<T> for [T] {
@@ -100,6 +246,31 @@ impl<T> OverlapChunksExt<T> for [T] {
         assert!(overlap < chunk_size, "overlap must be less than chunk size");
         OverlapChunks::new(self, chunk_size, overlap)
     }
+
+    #[inline]
+    fn padded_chunks(
+        &self,
+        chunk_size: usize,
+        overlap: usize,
+        padding: Padding,
+    ) -> PaddedChunks<'_, T>
+    where
+        T: Copy + Default,
+    {
+        assert!(overlap < chunk_size, "overlap must be less than chunk size");
+        PaddedChunks {
+            v: self,
+            chunk_size,
+            hop: chunk_size - overlap,
+            boundary: padding.boundary,
+            next_start: if padding.centered {
+                -((chunk_size / 2) as isize)
+            } else {
+                0
+            },
+            remaining: padding.chunks_count(self.len(), chunk_size, overlap),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -158,5 +329,79 @@ mod tests {
         assert_eq!(iter.next().unwrap(), &['e', 'm', 'i', 'p', 's', 'u']);
         assert_eq!(iter.next().unwrap(), &['p', 's', 'u', 'm']);
         assert!(iter.next().is_none());
+
+        assert_eq!(chunks_count(0, 4, 2), 0);
+        assert_eq!(chunks_count(10, 6, 3), 3);
+        // 87.5% overlap
+        assert_eq!(chunks_count(4800, 960, 840), 33);
+    }
+
+    #[test]
+    fn padded_chunks_test() {
+        let chunks = |padding: Padding, chunk_size: usize, overlap: usize| -> Vec<Vec<i32>> {
+            let slice = [1, 2, 3, 4, 5];
+            let iter = slice.padded_chunks(chunk_size, overlap, padding);
+            let count = iter.size_hint().0;
+            let chunks: Vec<Vec<i32>> = iter.map(|chunk| chunk.into_owned()).collect();
+            assert_eq!(chunks.len(), count);
+            chunks
+        };
+        let padding = |boundary, centered| Padding { boundary, centered };
+
+        assert_eq!(
+            chunks(padding(Boundary::Zero, false), 3, 1),
+            vec![vec![1, 2, 3], vec![3, 4, 5]]
+        );
+        // the example of `padded_chunks`, doctests don't run in the binary
+        assert_eq!(
+            chunks(padding(Boundary::Reflect, false), 3, 1),
+            vec![vec![1, 2, 3], vec![3, 4, 5]]
+        );
+        assert_eq!(
+            chunks(padding(Boundary::Zero, false), 4, 2),
+            vec![vec![1, 2, 3, 4], vec![3, 4, 5, 0]]
+        );
+        assert_eq!(
+            chunks(padding(Boundary::Edge, false), 4, 2),
+            vec![vec![1, 2, 3, 4], vec![3, 4, 5, 5]]
+        );
+        assert_eq!(
+            chunks(padding(Boundary::Reflect, false), 4, 2),
+            vec![vec![1, 2, 3, 4], vec![3, 4, 5, 4]]
+        );
+        assert_eq!(
+            chunks(padding(Boundary::Drop, false), 4, 2),
+            vec![vec![1, 2, 3, 4]]
+        );
+        assert!(chunks(padding(Boundary::Drop, false), 7, 2).is_empty());
+        // the chunk is longer than the slice
+        assert_eq!(
+            chunks(padding(Boundary::Reflect, false), 12, 0),
+            vec![vec![1, 2, 3, 4, 5, 4, 3, 2, 1, 2, 3, 4]]
+        );
+
+        // a chunk centered at each second element
+        assert_eq!(
+            chunks(padding(Boundary::Zero, true), 4, 2),
+            vec![vec![0, 0, 1, 2], vec![1, 2, 3, 4], vec![3, 4, 5, 0]]
+        );
+        assert_eq!(
+            chunks(padding(Boundary::Reflect, true), 4, 2),
+            vec![vec![3, 2, 1, 2], vec![1, 2, 3, 4], vec![3, 4, 5, 4]]
+        );
+        assert_eq!(
+            chunks(padding(Boundary::Edge, true), 3, 1),
+            vec![vec![1, 1, 2], vec![2, 3, 4], vec![4, 5, 5]]
+        );
+        assert_eq!(
+            chunks(padding(Boundary::Drop, true), 4, 2),
+            vec![vec![0, 0, 1, 2], vec![1, 2, 3, 4]]
+        );
+
+        let empty: [i32; 0] = [];
+        assert!(empty
+            .padded_chunks(4, 2, padding(Boundary::Reflect, true))
+            .next()
+            .is_none());
     }
 }
@@ -0,0 +1,368 @@
+//! Piano-roll overlay on top of the spectrum: one rectangle per note, aligned to the keys of the keyboard.
+//!
+//! Interactions:
+//! - left click on a note selects it, left click on an empty space clears the selection
+//! - dragging a note moves it in time and between keys, dragging its end changes the duration
+//! - `Delete` or `Backspace` removes the selected note
+//! - right click on an empty space adds a new note
+//!
+//! Edited notes are kept when the spectrum is updated, until a new source is loaded or the
+//! transcription is requested again.
+
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+
+use crate::{notes::Note, FftConfig, Keyboard, Spectrum, Transcription};
+
+/// The width of the note rectangle, the same as the width of a black key
+const NOTE_WIDTH: f32 = crate::BLACK_KEYS_SLOT_SIZE;
+/// The part of the note (from its end) that changes the duration when dragged
+const RESIZE_HANDLE: f32 = 6.0;
+/// The duration of notes added by the user
+const NEW_NOTE_DURATION_SEC: f32 = 0.5;
+/// Notes are drawn above the spectrum
+const NOTES_Z: f32 = 2.0;
+
+#[derive(Component)]
+pub(crate) struct PianoRollNote {
+    /// The index of the note in [`Transcription::notes`]
+    index: usize,
+}
+
+#[derive(Clone, Copy)]
+enum DragMode {
+    Move {
+        /// The time between the note start and the point where it was grabbed
+        grab_offset_sec: f32,
+    },
+    Resize,
+}
+
+#[derive(Resource)]
+pub(crate) struct PianoRoll {
+    mesh: Handle<Mesh>,
+    material: Handle<ColorMaterial>,
+    selected_material: Handle<ColorMaterial>,
+    selected: Option<usize>,
+    drag: Option<DragMode>,
+    /// [`Transcription::notes_generation`] the selection refers to
+    notes_generation: u32,
+}
+
+/// Drops the selection if notes were replaced since it was made. The piano roll is marked as
+/// changed only then, as it's checked on every frame.
+fn sync_selection(piano_roll: &mut ResMut<PianoRoll>, transcription: &Transcription) {
+    if piano_roll.notes_generation != transcription.notes_generation {
+        piano_roll.notes_generation = transcription.notes_generation;
+        piano_roll.selected = None;
+        piano_roll.drag = None;
+    }
+}
+
+pub(crate) fn setup_piano_roll(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    commands.insert_resource(PianoRoll {
+        // unit rectangle which is scaled to the note size
+        mesh: meshes.add(Rectangle::new(1.0, 1.0)),
+        material: materials.add(Color::rgba(0.2, 0.8, 0.3, 0.6)),
+        selected_material: materials.add(Color::rgba(1.0, 0.6, 0.1, 0.8)),
+        selected: None,
+        drag: None,
+        notes_generation: 0,
+    });
+}
+
+/// Maps time to the vertical position on the spectrum sprite and back.
+/// Time goes from the bottom of the sprite (next to the keyboard) to the top.
+pub(crate) struct RollGeometry {
+    bottom: f32,
+    height: f32,
+    offset_sec: f32,
+    duration_sec: f32,
+}
+
+impl RollGeometry {
+    pub(crate) fn new(
+        spectrum_transform: &Transform,
+        spectrum_sprite: &Sprite,
+        config: &FftConfig,
+    ) -> Self {
+        let height = spectrum_sprite.custom_size.unwrap_or_default().y;
+        Self {
+            bottom: spectrum_transform.translation.y - height / 2.0,
+            height,
+            offset_sec: config.offset_sec as f32,
+            duration_sec: config.duration_sec as f32,
+        }
+    }
+
+    pub(crate) fn time_to_y(&self, time_sec: f32) -> f32 {
+        self.bottom + (time_sec - self.offset_sec) / self.duration_sec * self.height
+    }
+
+    fn y_to_time(&self, y: f32) -> f32 {
+        self.offset_sec + (y - self.bottom) / self.height * self.duration_sec
+    }
+
+    /// Whether the time is within the visible time range
+    pub(crate) fn is_visible(&self, time_sec: f32) -> bool {
+        self.offset_sec <= time_sec && time_sec < self.offset_sec + self.duration_sec
+    }
+
+    /// The part of the note within the visible time range as (bottom, top) pair
+    pub(crate) fn visible_range(&self, note: &Note) -> Option<(f32, f32)> {
+        let start = note.start_sec.max(self.offset_sec);
+        let end = (note.start_sec + note.duration_sec).min(self.offset_sec + self.duration_sec);
+        (start < end).then(|| (self.time_to_y(start), self.time_to_y(end)))
+    }
+}
+
+/// Keeps note rectangles in sync with the transcription
+#[allow(clippy::type_complexity)]
+pub(crate) fn update_piano_roll(
+    mut commands: Commands,
+    mut piano_roll: ResMut<PianoRoll>,
+    transcription: Res<Transcription>,
+    config: Res<FftConfig>,
+    spectrum: Query<(&Transform, &Sprite), (With<Spectrum>, Without<PianoRollNote>)>,
+    keyboard: Query<&Transform, (With<Keyboard>, Without<PianoRollNote>)>,
+    mut roll_notes: Query<(
+        Entity,
+        &PianoRollNote,
+        &mut Transform,
+        &mut Visibility,
+        &mut Handle<ColorMaterial>,
+    )>,
+) {
+    if !transcription.is_changed() && !config.is_changed() && !piano_roll.is_changed() {
+        return;
+    }
+    let (Ok((spectrum_transform, spectrum_sprite)), Ok(keyboard_transform)) =
+        (spectrum.get_single(), keyboard.get_single())
+    else {
+        return;
+    };
+    let geometry = RollGeometry::new(spectrum_transform, spectrum_sprite, &config);
+
+    sync_selection(&mut piano_roll, &transcription);
+    if piano_roll
+        .selected
+        .is_some_and(|selected| selected >= transcription.notes.len())
+    {
+        piano_roll.selected = None;
+        piano_roll.drag = None;
+    }
+
+    // Respawn all rectangles if the number of notes has changed, otherwise just update them
+    if roll_notes.iter().count() != transcription.notes.len() {
+        for (entity, ..) in roll_notes.iter() {
+            commands.entity(entity).despawn();
+        }
+        for index in 0..transcription.notes.len() {
+            commands.spawn((
+                MaterialMesh2dBundle {
+                    mesh: piano_roll.mesh.clone().into(),
+                    material: piano_roll.material.clone(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                PianoRollNote { index },
+            ));
+        }
+        // Spawned entities are available only on the next frame
+        piano_roll.set_changed();
+        return;
+    }
+
+    for (_, roll_note, mut transform, mut visibility, mut material) in roll_notes.iter_mut() {
+        let Some(note) = transcription.notes.get(roll_note.index) else {
+            continue;
+        };
+        let Some((bottom, top)) = geometry.visible_range(note) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+
+        *visibility = Visibility::Inherited;
+        let x = keyboard_transform.translation.x + crate::key_to_keyboard_pos_x(note.key);
+        *transform = Transform::from_translation(Vec3::new(x, (bottom + top) / 2.0, NOTES_Z))
+            .with_scale(Vec3::new(NOTE_WIDTH, top - bottom, 1.0));
+        *material = if piano_roll.selected == Some(roll_note.index) {
+            piano_roll.selected_material.clone()
+        } else {
+            piano_roll.material.clone()
+        };
+    }
+}
+
+/// Handles mouse and keyboard interactions with notes on the piano roll
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn piano_roll_input(
+    mut contexts: EguiContexts,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut piano_roll: ResMut<PianoRoll>,
+    mut transcription: ResMut<Transcription>,
+    config: Res<FftConfig>,
+    spectrum: Query<(&Transform, &Sprite), With<Spectrum>>,
+    keyboard: Query<&Transform, With<Keyboard>>,
+) {
+    sync_selection(&mut piano_roll, &transcription);
+    let ctx = contexts.ctx_mut();
+    if !ctx.wants_keyboard_input()
+        && (keyboard_input.just_pressed(KeyCode::Delete)
+            || keyboard_input.just_pressed(KeyCode::Backspace))
+    {
+        if let Some(selected) = piano_roll
+            .selected
+            .take()
+            .filter(|selected| *selected < transcription.notes.len())
+        {
+            transcription.notes.remove(selected);
+            transcription.edited = true;
+            piano_roll.drag = None;
+        }
+    }
+
+    if mouse_button_input.just_released(MouseButton::Left) {
+        piano_roll.drag = None;
+    }
+
+    let (Ok((spectrum_transform, spectrum_sprite)), Ok(keyboard_transform)) =
+        (spectrum.get_single(), keyboard.get_single())
+    else {
+        return;
+    };
+    let Some(cursor_pos) = crate::cursor_world_pos(windows.single()) else {
+        return;
+    };
+    let geometry = RollGeometry::new(spectrum_transform, spectrum_sprite, &config);
+    let spectrum_size = spectrum_sprite.custom_size.unwrap_or_default();
+    let in_spectrum = (cursor_pos - spectrum_transform.translation.xy())
+        .abs()
+        .cmplt(spectrum_size / 2.0)
+        .all();
+
+    // Keys are resolved at the top of the keyboard, where the black keys are
+    let cursor_key = || {
+        let x = cursor_pos.x - keyboard_transform.translation.x;
+        crate::keyboard_pos_to_key(Vec2::new(x, crate::KEYBOARD_SIZE.y / 2.0))
+    };
+    let cursor_time = geometry.y_to_time(cursor_pos.y);
+
+    // Continue dragging even if the cursor left the spectrum
+    if let (Some(drag), Some(selected)) = (piano_roll.drag, piano_roll.selected) {
+        let Some(&before) = transcription.notes.get(selected) else {
+            return;
+        };
+        let mut note = before;
+        match drag {
+            DragMode::Move { grab_offset_sec } => {
+                note.start_sec = (cursor_time - grab_offset_sec).max(0.0);
+                if let Some(key) = cursor_key() {
+                    note.key = key;
+                }
+            }
+            DragMode::Resize => {
+                let min_duration = geometry.y_to_time(geometry.bottom + 1.0) - geometry.offset_sec;
+                note.duration_sec = (cursor_time - note.start_sec).max(min_duration);
+            }
+        }
+        // holding a note without moving it isn't an edit
+        if note != before {
+            transcription.notes[selected] = note;
+            transcription.edited = true;
+        }
+        return;
+    }
+
+    // Shift+drag selects keys to isolate instead
+    if !in_spectrum
+        || ctx.is_pointer_over_area()
+        || keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight])
+    {
+        return;
+    }
+
+    let note_under_cursor = transcription.notes.iter().position(|note| {
+        let x = keyboard_transform.translation.x + crate::key_to_keyboard_pos_x(note.key);
+        (cursor_pos.x - x).abs() <= NOTE_WIDTH / 2.0
+            && geometry
+                .visible_range(note)
+                .is_some_and(|(bottom, top)| bottom <= cursor_pos.y && cursor_pos.y <= top)
+    });
+
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        piano_roll.selected = note_under_cursor;
+        piano_roll.drag = note_under_cursor.map(|index| {
+            let note = &transcription.notes[index];
+            let top = geometry.time_to_y(note.start_sec + note.duration_sec);
+            if top - cursor_pos.y <= RESIZE_HANDLE {
+                DragMode::Resize
+            } else {
+                DragMode::Move {
+                    grab_offset_sec: cursor_time - note.start_sec,
+                }
+            }
+        });
+    } else if mouse_button_input.just_pressed(MouseButton::Right) && note_under_cursor.is_none() {
+        if let Some(key) = cursor_key() {
+            transcription.notes.push(Note {
+                key,
+                start_sec: cursor_time,
+                duration_sec: NEW_NOTE_DURATION_SEC,
+            });
+            transcription.edited = true;
+            piano_roll.selected = Some(transcription.notes.len() - 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn roll_geometry_test() {
+        let geometry = RollGeometry {
+            bottom: -100.0,
+            height: 200.0,
+            offset_sec: 10.0,
+            duration_sec: 20.0,
+        };
+        assert_eq!(geometry.time_to_y(10.0), -100.0);
+        assert_eq!(geometry.time_to_y(20.0), 0.0);
+        assert_eq!(geometry.time_to_y(30.0), 100.0);
+        assert_eq!(geometry.y_to_time(50.0), 25.0);
+        assert!(geometry.is_visible(10.0));
+        assert!(!geometry.is_visible(9.9));
+        assert!(!geometry.is_visible(30.0));
+
+        let note = |start_sec, duration_sec| Note {
+            key: 48,
+            start_sec,
+            duration_sec,
+        };
+        assert_eq!(
+            geometry.visible_range(&note(12.0, 1.0)),
+            Some((-80.0, -70.0))
+        );
+        // partially visible
+        assert_eq!(
+            geometry.visible_range(&note(5.0, 6.0)),
+            Some((-100.0, -90.0))
+        );
+        assert_eq!(
+            geometry.visible_range(&note(29.0, 6.0)),
+            Some((90.0, 100.0))
+        );
+        // out of range
+        assert_eq!(geometry.visible_range(&note(5.0, 5.0)), None);
+        assert_eq!(geometry.visible_range(&note(30.0, 1.0)), None);
+    }
+}
@@ -0,0 +1,225 @@
+//! Time-frequency reassigned spectrogram.
+//! https://doi.org/10.1109/78.382394 (Auger & Flandrin, "Improving the readability of
+//! time-frequency and time-scale representations by the reassignment method", 1995)
+//!
+//! The energy of each bin is moved from the center of its cell to the center of gravity of the
+//! signal within it: the instantaneous frequency and the group delay. Both come from spectra with
+//! the derivative and the time-weighted versions of the window, so sharp lines of close partials
+//! need no huge windows.
+
+use std::{f32::consts::PI, ops::Deref, sync::Arc};
+
+use realfft::{num_complex::Complex, RealFftPlanner, RealToComplex};
+
+use crate::window_fn;
+
+/// Bins with less energy have no meaningful phase and are skipped
+const MIN_ENERGY: f32 = 1e-10;
+
+/// A reassigned bin
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Point {
+    /// The fractional bin of the instantaneous frequency
+    pub(crate) bin: f32,
+    /// The group delay in samples relative to the center of the frame
+    pub(crate) offset: f32,
+    /// The energy of the bin, as with the rectangular window for sines
+    pub(crate) energy: f32,
+}
+
+pub(crate) struct Reassignment {
+    r2c: Arc<dyn RealToComplex<f32>>,
+    /// The Hann window, its derivative and the window weighted by time from the center
+    windows: [Vec<f32>; 3],
+    /// Amplitudes are divided by the coherent gain of the window
+    gain: f32,
+    input_buf: Vec<f32>,
+    output_bufs: [Vec<Complex<f32>>; 3],
+    scratch_buf: Vec<Complex<f32>>,
+}
+
+impl Reassignment {
+    /// Reassignment of frames of `window_size` samples padded with zeros to `fft_size`
+    pub(crate) fn new(window_size: usize, fft_size: usize) -> Self {
+        let window = window_fn::hann(window_size);
+        let center = (window_size as f32 - 1.0) / 2.0;
+        let time_weighted = window
+            .iter()
+            .enumerate()
+            .map(|(i, w)| (i as f32 - center) * w)
+            .collect();
+        let gain = window_fn::coherent_gain(&window);
+        let windows = [
+            window,
+            window_fn::hann_derivative(window_size),
+            time_weighted,
+        ];
+
+        let r2c = RealFftPlanner::<f32>::new().plan_fft_forward(fft_size);
+        Self {
+            input_buf: r2c.make_input_vec(),
+            output_bufs: std::array::from_fn(|_| r2c.make_output_vec()),
+            scratch_buf: r2c.make_scratch_vec(),
+            r2c,
+            windows,
+            gain,
+        }
+    }
+
+    /// Reassigned points of bins below `bins` of the frame
+    pub(crate) fn frame(&mut self, chunk: &[f32], bins: usize) -> Vec<Point> {
+        for (window, output_buf) in self.windows.iter().zip(&mut self.output_bufs) {
+            for ((input, sample), window) in self.input_buf.iter_mut().zip(chunk).zip(window) {
+                *input = sample * window;
+            }
+            // the transform leaves garbage in its input, so the padding is zeroed every time
+            self.input_buf[window.len()..].fill(0.0);
+            self.r2c
+                .process_with_scratch(&mut self.input_buf, output_buf, &mut self.scratch_buf)
+                .unwrap();
+        }
+
+        let fft_size = self.input_buf.len() as f32;
+        let [spectrum, derivative, time_weighted] = &self.output_bufs;
+        spectrum
+            .iter()
+            .zip(derivative)
+            .zip(time_weighted)
+            .take(bins)
+            .enumerate()
+            .filter_map(|(bin, ((x, dx), tx))| {
+                let energy = x.norm_sqr();
+                if energy < MIN_ENERGY {
+                    return None;
+                }
+                // the frequency correction is in radians per sample
+                let frequency = -(dx / x).im;
+                Some(Point {
+                    bin: bin as f32 + frequency * fft_size / (2.0 * PI),
+                    offset: (tx / x).re,
+                    energy: energy / (self.gain * self.gain),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Reassigned spectrogram of `rows` frames of `bins` magnitudes. Frames are `hop` samples apart.
+pub(crate) fn reassigned_frames<C: Deref<Target = [f32]>>(
+    chunks: impl Iterator<Item = C>,
+    window_size: usize,
+    fft_size: usize,
+    hop: usize,
+    bins: usize,
+    rows: usize,
+) -> Vec<Vec<f32>> {
+    let mut reassignment = Reassignment::new(window_size, fft_size);
+    let mut energies = vec![vec![0.0; bins]; rows];
+    for (frame, chunk) in chunks.take(rows).enumerate() {
+        for point in reassignment.frame(&chunk, bins) {
+            let row = frame as f32 + point.offset / hop as f32;
+            let (row, bin) = (row.round(), point.bin.round());
+            if (0.0..rows as f32).contains(&row) && (0.0..bins as f32).contains(&bin) {
+                energies[row as usize][bin as usize] += point.energy;
+            }
+        }
+    }
+
+    for frame in &mut energies {
+        for value in frame {
+            *value = value.sqrt();
+        }
+    }
+    energies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlap_chunks::OverlapChunksExt;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn frame_test() {
+        // a sine between bins 32 and 33
+        let sample_rate = 8000.0;
+        let frequency = 1015.0;
+        let samples: Vec<f32> = (0..256)
+            .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate).sin())
+            .collect();
+
+        for fft_size in [256, 1024] {
+            let bin_hz = sample_rate / fft_size as f32;
+            let points = Reassignment::new(256, fft_size).frame(&samples, fft_size / 4);
+            let peak = points
+                .iter()
+                .max_by(|a, b| a.energy.total_cmp(&b.energy))
+                .unwrap();
+            // the amplitude is the half of the window length
+            assert!((peak.energy.sqrt() - 128.0).abs() < 30.0, "{}", peak.energy);
+            // all strong bins point to the frequency of the sine at the center of the frame
+            for point in points.iter().filter(|p| p.energy > peak.energy / 10.0) {
+                assert!(
+                    (point.bin * bin_hz - frequency).abs() < 1.0,
+                    "{fft_size} {point:?}"
+                );
+                assert!(point.offset.abs() < 1.0, "{fft_size} {point:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn padded_frames_test() {
+        let sample_rate = 8000.0;
+        let frequency = 1015.0;
+        let samples: Vec<f32> = (0..2048)
+            .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate).sin())
+            .collect();
+
+        // the padding is much longer than the window and stays zero in every transform
+        let fft_size = 8192;
+        let bin_hz = sample_rate / fft_size as f32;
+        let mut reassignment = Reassignment::new(1024, fft_size);
+        for chunk in samples.chunks(1024) {
+            let points = reassignment.frame(chunk, fft_size / 4);
+            let peak = points
+                .iter()
+                .max_by(|a, b| a.energy.total_cmp(&b.energy))
+                .unwrap();
+            assert!((peak.energy.sqrt() - 512.0).abs() < 50.0, "{}", peak.energy);
+            for point in points.iter().filter(|p| p.energy > peak.energy / 10.0) {
+                assert!((point.bin * bin_hz - frequency).abs() < 1.0, "{point:?}");
+                assert!(point.offset.abs() < 1.0, "{point:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn reassigned_frames_test() {
+        let sample_rate = 8000.0;
+        let mut samples: Vec<f32> = (0..4096)
+            .map(|i| (2.0 * PI * 1015.0 * i as f32 / sample_rate).sin())
+            .collect();
+        // a click in the middle
+        samples[2000] += 50.0;
+
+        let frames = reassigned_frames(samples.overlap_chunks(256, 192), 256, 256, 64, 128, 61);
+        assert_eq!(frames.len(), 61);
+        assert_eq!(frames[0].len(), 128);
+
+        // the sine is a sharp line at the nearest bin, 1015 Hz is 32.48 bins
+        let column_energy = |bin: usize| -> f32 { frames.iter().map(|row| row[bin].powi(2)).sum() };
+        let total: f32 = (20..45).map(column_energy).sum();
+        let line = column_energy(32);
+        assert!(line > 0.7 * total, "{line} {total}");
+
+        // the click is a sharp line at its row, its frames are centered at 128 + 64 * row
+        let row_energy = |row: usize| -> f32 { frames[row][50..].iter().map(|v| v * v).sum() };
+        let click_row = (0..61)
+            .max_by(|a, b| row_energy(*a).total_cmp(&row_energy(*b)))
+            .unwrap();
+        assert_eq!(click_row, (2000 - 128 + 32) / 64);
+        assert!(row_energy(click_row) > 4.0 * row_energy(click_row - 1));
+        assert!(row_energy(click_row) > 4.0 * row_energy(click_row + 1));
+    }
+}
@@ -0,0 +1,279 @@
+//! Scala tuning files: scales (.scl) and keyboard mappings (.kbm).
+//! https://www.huygens-fokker.org/scala/scl_format.html
+//! https://www.huygens-fokker.org/scala/help.htm#mappings
+//!
+//! Lines starting with `!` are comments. A scale lists pitches of its degrees above the implied
+//! unison, either in cents (with a dot) or as ratios, and the last pitch is the period (usually 2/1).
+
+use anyhow::{bail, Context, Result};
+
+/// The MIDI note number of the first piano key, A0
+pub(crate) const MIDI_A0: i32 = 21;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Scale {
+    pub(crate) description: String,
+    /// Pitches of degrees 1..=n in cents above the degree 0, the last one is the period
+    pub(crate) pitches: Vec<f32>,
+}
+
+impl Scale {
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().filter(|line| !line.starts_with('!'));
+        let description = lines.next().context("missing description")?.trim();
+        let count: usize = first_token(lines.next().context("missing number of notes")?)
+            .parse()
+            .context("invalid number of notes")?;
+        if count == 0 {
+            bail!("the scale has no notes");
+        }
+
+        let pitches = lines
+            .take(count)
+            .map(|line| parse_pitch(line).with_context(|| format!("invalid pitch {line:?}")))
+            .collect::<Result<Vec<_>>>()?;
+        if pitches.len() != count {
+            bail!("expected {count} notes, found {}", pitches.len());
+        }
+        if pitches[count - 1] <= 0.0 {
+            bail!("the period must be above the unison");
+        }
+
+        Ok(Self {
+            description: description.to_owned(),
+            pitches,
+        })
+    }
+
+    /// The number of degrees in the period
+    pub(crate) fn len(&self) -> i32 {
+        self.pitches.len() as i32
+    }
+
+    /// The pitch of the degree in cents above the degree 0, degrees outside 0..n repeat by periods
+    pub(crate) fn cents(&self, degree: i32) -> f32 {
+        let period = self.pitches[self.pitches.len() - 1];
+        let step = degree.rem_euclid(self.len()) as usize;
+        let step_cents = if step == 0 {
+            0.0
+        } else {
+            self.pitches[step - 1]
+        };
+        degree.div_euclid(self.len()) as f32 * period + step_cents
+    }
+}
+
+/// Maps MIDI notes to scale degrees and sets the absolute pitch of the scale
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct KeyboardMapping {
+    /// Notes outside this range are not mapped
+    pub(crate) first_note: i32,
+    pub(crate) last_note: i32,
+    /// The note the degree 0 is mapped to
+    pub(crate) middle_note: i32,
+    /// The note that sounds at `reference_hz`
+    pub(crate) reference_note: i32,
+    pub(crate) reference_hz: f32,
+    /// The degree the mapping pattern repeats at, the scale period if `None`
+    pub(crate) octave_degree: Option<i32>,
+    /// Degrees of the consecutive notes starting from the middle one, `None` for unmapped notes.
+    /// Empty for the linear mapping, where each note is the next degree.
+    pub(crate) mapping: Vec<Option<i32>>,
+}
+
+impl Default for KeyboardMapping {
+    /// The linear mapping starting from C4 with A4 at 440 Hz
+    fn default() -> Self {
+        Self {
+            first_note: 0,
+            last_note: 127,
+            middle_note: 60,
+            reference_note: 69,
+            reference_hz: 440.0,
+            octave_degree: None,
+            mapping: vec![],
+        }
+    }
+}
+
+impl KeyboardMapping {
+    pub(crate) fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .filter(|line| !line.starts_with('!'))
+            .map(first_token);
+        let mut field = |name: &str| lines.next().with_context(|| format!("missing {name}"));
+
+        let map_size: usize = field("map size")?.parse().context("invalid map size")?;
+        let first_note = field("first note")?.parse().context("invalid first note")?;
+        let last_note = field("last note")?.parse().context("invalid last note")?;
+        let middle_note = field("middle note")?
+            .parse()
+            .context("invalid middle note")?;
+        let reference_note = field("reference note")?
+            .parse()
+            .context("invalid reference note")?;
+        let reference_hz: f32 = field("reference frequency")?
+            .parse()
+            .context("invalid reference frequency")?;
+        if reference_hz <= 0.0 {
+            bail!("the reference frequency must be positive");
+        }
+        let octave_degree = match field("octave degree")?
+            .parse()
+            .context("invalid octave degree")?
+        {
+            0 => None,
+            degree => Some(degree),
+        };
+
+        // Missing entries at the end of the mapping are unmapped notes
+        let mut mapping = lines
+            .take(map_size)
+            .map(|entry| match entry {
+                "x" | "" => Ok(None),
+                entry => entry.parse().map(Some).context("invalid mapping entry"),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        mapping.resize(map_size, None);
+
+        Ok(Self {
+            first_note,
+            last_note,
+            middle_note,
+            reference_note,
+            reference_hz,
+            octave_degree,
+            mapping,
+        })
+    }
+
+    /// The scale degree of the MIDI note, `None` if the note is not mapped
+    pub(crate) fn degree(&self, note: i32, scale: &Scale) -> Option<i32> {
+        if !(self.first_note..=self.last_note).contains(&note) {
+            return None;
+        }
+
+        let offset = note - self.middle_note;
+        if self.mapping.is_empty() {
+            return Some(offset);
+        }
+        let size = self.mapping.len() as i32;
+        let degree = self.mapping[offset.rem_euclid(size) as usize]?;
+        let octave_degree = self.octave_degree.unwrap_or(scale.len());
+        Some(degree + offset.div_euclid(size) * octave_degree)
+    }
+}
+
+/// The first whitespace separated token of the line, the rest of the line is a comment
+fn first_token(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or_default()
+}
+
+/// Parses a pitch in cents (`701.955`) or as a ratio (`3/2` or `2`) into cents
+fn parse_pitch(line: &str) -> Result<f32> {
+    let token = first_token(line);
+    if token.contains('.') {
+        return Ok(token.parse()?);
+    }
+
+    let (numerator, denominator) = token.split_once('/').unwrap_or((token, "1"));
+    let (numerator, denominator): (u32, u32) = (numerator.parse()?, denominator.parse()?);
+    if numerator == 0 || denominator == 0 {
+        bail!("the ratio must be positive");
+    }
+    Ok(1200.0 * (numerator as f32 / denominator as f32).log2())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn scale_test() {
+        let scale = Scale::parse(
+            "! meanquar.scl\n\
+             !\n\
+             1/4-comma meantone scale. Pietro Aaron's temperament (1523)\n \
+             12\n\
+             !\n \
+             76.04900\n \
+             193.15686\n \
+             310.26471\n \
+             5/4\n \
+             503.42157 fourth\n \
+             579.47057\n \
+             696.57843\n \
+             25/16\n \
+             889.73529\n \
+             1006.84314\n \
+             1082.89214\n \
+             2/1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            scale.description,
+            "1/4-comma meantone scale. Pietro Aaron's temperament (1523)"
+        );
+        assert_eq!(scale.len(), 12);
+        assert_eq!(scale.cents(0), 0.0);
+        assert!((scale.cents(4) - 386.3137).abs() < 1e-3);
+        assert_eq!(scale.cents(5), 503.42157);
+        assert!((scale.cents(12) - 1200.0).abs() < 1e-3);
+        assert!((scale.cents(16) - 1586.3137).abs() < 1e-3);
+        assert!((scale.cents(-1) + 117.10786).abs() < 1e-3);
+
+        assert!(Scale::parse("").is_err());
+        assert!(Scale::parse("empty\n0\n").is_err());
+        assert!(Scale::parse("short\n3\n100.0\n200.0\n").is_err());
+        assert!(Scale::parse("zero\n1\n0/1\n").is_err());
+        assert!(Scale::parse("garbage\n1\nabc\n").is_err());
+    }
+
+    #[test]
+    fn keyboard_mapping_test() {
+        // Pentatonic scale on black keys, white keys are unmapped
+        let scale = Scale::parse("pentatonic\n5\n200.0\n300.0\n500.0\n700.0\n2/1\n").unwrap();
+        let mapping = KeyboardMapping::parse(
+            "! black keys\n\
+             12\n\
+             0\n\
+             127\n\
+             61\n\
+             69\n\
+             442.0\n\
+             5\n\
+             0\n\
+             x\n\
+             1\n\
+             x\n\
+             x\n\
+             2\n\
+             x\n\
+             3\n\
+             x\n\
+             4\n\
+             x\n",
+        )
+        .unwrap();
+        assert_eq!(mapping.reference_note, 69);
+        assert_eq!(mapping.reference_hz, 442.0);
+        assert_eq!(mapping.mapping.len(), 12);
+        assert_eq!(mapping.degree(61, &scale), Some(0));
+        assert_eq!(mapping.degree(62, &scale), None);
+        assert_eq!(mapping.degree(63, &scale), Some(1));
+        assert_eq!(mapping.degree(70, &scale), Some(4));
+        assert_eq!(mapping.degree(73, &scale), Some(5));
+        assert_eq!(mapping.degree(58, &scale), Some(-1));
+        assert_eq!(scale.cents(mapping.degree(73, &scale).unwrap()), 1200.0);
+
+        let linear = KeyboardMapping::default();
+        assert_eq!(linear.degree(60, &scale), Some(0));
+        assert_eq!(linear.degree(65, &scale), Some(5));
+        assert_eq!(linear.degree(128, &scale), None);
+
+        assert!(KeyboardMapping::parse("12\n0\n127\n60\n69\n").is_err());
+        assert!(KeyboardMapping::parse("0\n0\n127\n60\n69\n-440.0\n0\n").is_err());
+    }
+}
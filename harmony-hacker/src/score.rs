@@ -0,0 +1,730 @@
+//! Rendering of notes as a grand staff using egui's painter.
+//!
+//! Vertical positions are measured in diatonic steps from the middle C (C4), so the lines of the
+//! treble staff are at steps 2, 4, .., 10 (E4..F5) and the lines of the bass staff at -10, -8, .., -2 (G2..A3).
+
+use bevy_egui::egui::{
+    self,
+    epaint::{CubicBezierShape, QuadraticBezierShape},
+    Color32, Painter, Pos2, Rect, Shape, Stroke, Vec2,
+};
+
+use crate::{chord::ChordSegment, notes::Note};
+
+/// The vertical distance between two adjacent diatonic steps, i.e. the half of the staff space
+const HALF_SPACE: f32 = 4.0;
+/// Additional space between the treble and the bass staff
+const STAFF_GAP: f32 = 6.0 * HALF_SPACE;
+/// The length of a stem from the notehead center
+const STEM_LENGTH: f32 = 7.0 * HALF_SPACE;
+/// The width of the area reserved for the clefs at the beginning of the staff
+const CLEF_WIDTH: f32 = 36.0;
+/// The width reserved for each accidental of the key signature
+const KEY_SIGNATURE_STEP: f32 = 8.0;
+/// Radius of the notehead
+const NOTEHEAD_SIZE: Vec2 = Vec2 {
+    x: 1.35 * HALF_SPACE,
+    y: HALF_SPACE,
+};
+/// The height of the whole grand staff with some space for ledger lines
+pub(crate) const SCORE_HEIGHT: f32 = 2.0 * 16.0 * HALF_SPACE + STAFF_GAP + 2.0 * STEM_LENGTH;
+
+/// Steps of the sharps in the key signature on the treble staff, in order of appearance (F C G D A E B)
+const SHARPS_STEPS: [i32; 7] = [10, 7, 11, 8, 5, 9, 6];
+/// Steps of the flats in the key signature on the treble staff, in order of appearance (B E A D G C F)
+const FLATS_STEPS: [i32; 7] = [6, 9, 5, 8, 4, 7, 3];
+/// Letters (0 is C, 6 is B) altered by the key signature with sharps, in order of appearance
+const SHARPS_ORDER: [i32; 7] = [3, 0, 4, 1, 5, 2, 6];
+/// Letters (0 is C, 6 is B) altered by the key signature with flats, in order of appearance
+const FLATS_ORDER: [i32; 7] = [6, 2, 5, 1, 4, 0, 3];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Clef {
+    Treble,
+    Bass,
+}
+
+impl Clef {
+    /// The step of the middle line of the staff
+    fn middle_line(self) -> i32 {
+        match self {
+            Clef::Treble => 6,
+            Clef::Bass => -6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Accidental {
+    Sharp,
+    Flat,
+    Natural,
+}
+
+/// Where and how a note is drawn on the grand staff
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct StaffPosition {
+    pub(crate) clef: Clef,
+    /// Diatonic steps from the middle C
+    pub(crate) step: i32,
+    pub(crate) accidental: Option<Accidental>,
+}
+
+/// Resolves the key number 0..88 to the position on the grand staff.
+///
+/// `key_signature` is the number of sharps (positive) or flats (negative) in the key signature.
+/// Black keys are spelled with sharps in sharp and neutral keys and with flats in flat keys.
+/// Accidentals are not carried through the bar, so each altered note gets its own sign.
+pub(crate) fn staff_position(key: u8, key_signature: i8) -> StaffPosition {
+    // The key number 39 is C4
+    let semitones = key as i32 + 9;
+    let octave = semitones / 12 - 4;
+    let pitch_class = semitones % 12;
+
+    // (letter, alteration) pairs for each pitch class
+    const SHARPS: [(i32, i32); 12] = [
+        (0, 0),
+        (0, 1),
+        (1, 0),
+        (1, 1),
+        (2, 0),
+        (3, 0),
+        (3, 1),
+        (4, 0),
+        (4, 1),
+        (5, 0),
+        (5, 1),
+        (6, 0),
+    ];
+    const FLATS: [(i32, i32); 12] = [
+        (0, 0),
+        (1, -1),
+        (1, 0),
+        (2, -1),
+        (2, 0),
+        (3, 0),
+        (4, -1),
+        (4, 0),
+        (5, -1),
+        (5, 0),
+        (6, -1),
+        (6, 0),
+    ];
+    let (letter, alteration) = if key_signature < 0 {
+        FLATS[pitch_class as usize]
+    } else {
+        SHARPS[pitch_class as usize]
+    };
+
+    let signature_alteration = if key_signature > 0 {
+        SHARPS_ORDER[..key_signature.min(7) as usize]
+            .contains(&letter)
+            .then_some(1)
+    } else {
+        FLATS_ORDER[..(-key_signature).min(7) as usize]
+            .contains(&letter)
+            .then_some(-1)
+    }
+    .unwrap_or(0);
+
+    let accidental = match alteration {
+        _ if alteration == signature_alteration => None,
+        1 => Some(Accidental::Sharp),
+        -1 => Some(Accidental::Flat),
+        _ => Some(Accidental::Natural),
+    };
+
+    let step = octave * 7 + letter;
+    StaffPosition {
+        clef: if step >= 0 { Clef::Treble } else { Clef::Bass },
+        step,
+        accidental,
+    }
+}
+
+/// Returns the steps of ledger lines needed to draw a note at `step` on the given staff
+pub(crate) fn ledger_lines(clef: Clef, step: i32) -> Vec<i32> {
+    let (bottom, top) = match clef {
+        Clef::Treble => (2, 10),
+        Clef::Bass => (-10, -2),
+    };
+    if step < bottom {
+        (step..bottom).filter(|s| s.rem_euclid(2) == 0).collect()
+    } else if step > top {
+        (top + 1..=step).filter(|s| s.rem_euclid(2) == 0).collect()
+    } else {
+        vec![]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum NoteValue {
+    Sixteenth,
+    Eighth,
+    Quarter,
+    Half,
+    Whole,
+}
+
+impl NoteValue {
+    /// Quantizes the duration in beats (quarter notes) to the closest note value
+    pub(crate) fn from_beats(beats: f32) -> Self {
+        // The middle points between note values in log scale
+        match beats {
+            b if b >= 2.0 * std::f32::consts::SQRT_2 => NoteValue::Whole,
+            b if b >= std::f32::consts::SQRT_2 => NoteValue::Half,
+            b if b >= std::f32::consts::FRAC_1_SQRT_2 => NoteValue::Quarter,
+            b if b >= std::f32::consts::FRAC_1_SQRT_2 / 2.0 => NoteValue::Eighth,
+            _ => NoteValue::Sixteenth,
+        }
+    }
+
+    /// Number of beams or flags
+    fn beams(self) -> usize {
+        match self {
+            NoteValue::Sixteenth => 2,
+            NoteValue::Eighth => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Notes on the same staff that start at the same time and share a stem
+struct Chord {
+    clef: Clef,
+    x: f32,
+    /// The index of the sixteenth note on the grid, used for grouping notes to beams
+    tick: i64,
+    value: NoteValue,
+    heads: Vec<(i32, Option<Accidental>)>,
+}
+
+impl Chord {
+    /// The stem goes up if the notes are mostly below the middle line
+    fn stem_up(&self) -> bool {
+        let sum: i32 = self
+            .heads
+            .iter()
+            .map(|(step, _)| step - self.clef.middle_line())
+            .sum();
+        sum < 0
+    }
+}
+
+/// What and how to draw on the grand staff
+pub(crate) struct ScoreView<'a> {
+    pub(crate) notes: &'a [Note],
+    /// The time of the left edge of the score in seconds
+    pub(crate) offset_sec: f32,
+    /// The time range visible on the score in seconds
+    pub(crate) duration_sec: f32,
+    /// Number of sharps (positive) or flats (negative)
+    pub(crate) key_signature: i8,
+    /// The duration of a beat (quarter note) in seconds
+    pub(crate) beat_sec: f32,
+    /// Beat timestamps in seconds, every 4th beat starts a new bar
+    pub(crate) beats: &'a [f32],
+    /// Chord symbols written above the treble staff
+    pub(crate) chord_segments: &'a [ChordSegment],
+}
+
+impl ScoreView<'_> {
+    /// Start times of bars: every 4th tracked beat or, without tracked beats, the fixed grid of 4
+    /// beats of `beat_sec` over the visible range
+    fn bars(&self) -> Vec<f32> {
+        if !self.beats.is_empty() {
+            return self.beats.iter().step_by(4).copied().collect();
+        }
+        if self.beat_sec <= 0.0 {
+            return Vec::new();
+        }
+        let bar_sec = 4.0 * self.beat_sec;
+        let first = (self.offset_sec / bar_sec).ceil() as u32;
+        let last = ((self.offset_sec + self.duration_sec) / bar_sec) as u32;
+        (first..=last).map(|bar| bar as f32 * bar_sec).collect()
+    }
+
+    /// Draws the grand staff filling the available width
+    pub(crate) fn show(&self, ui: &mut egui::Ui) {
+        let size = Vec2::new(ui.available_width(), SCORE_HEIGHT);
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 0.0, Color32::WHITE);
+
+        let layout = Layout::new(rect);
+        let stroke = Stroke::new(1.0, Color32::BLACK);
+
+        // Staff lines and the clefs
+        for clef in [Clef::Treble, Clef::Bass] {
+            for step in (clef.middle_line() - 4..=clef.middle_line() + 4).step_by(2) {
+                let y = layout.y(clef, step);
+                painter.line_segment(
+                    [Pos2::new(rect.left(), y), Pos2::new(rect.right(), y)],
+                    stroke,
+                );
+            }
+        }
+        painter.line_segment(
+            [
+                Pos2::new(rect.left(), layout.y(Clef::Treble, 10)),
+                Pos2::new(rect.left(), layout.y(Clef::Bass, -10)),
+            ],
+            Stroke::new(2.0, Color32::BLACK),
+        );
+        draw_treble_clef(&painter, &layout, rect.left() + 6.0);
+        draw_bass_clef(&painter, &layout, rect.left() + 6.0);
+
+        // Key signature
+        let (signature_steps, accidental) = if self.key_signature >= 0 {
+            (&SHARPS_STEPS, Accidental::Sharp)
+        } else {
+            (&FLATS_STEPS, Accidental::Flat)
+        };
+        let accidentals_count = self.key_signature.unsigned_abs().min(7) as usize;
+        for (i, step) in signature_steps[..accidentals_count].iter().enumerate() {
+            let x = rect.left() + CLEF_WIDTH + (i as f32 + 0.5) * KEY_SIGNATURE_STEP;
+            draw_accidental(
+                &painter,
+                accidental,
+                Pos2::new(x, layout.y(Clef::Treble, *step)),
+            );
+            draw_accidental(
+                &painter,
+                accidental,
+                Pos2::new(x, layout.y(Clef::Bass, step - 14)),
+            );
+        }
+
+        // Notes area
+        let notes_left =
+            rect.left() + CLEF_WIDTH + (accidentals_count as f32 + 1.0) * KEY_SIGNATURE_STEP;
+        let px_per_sec = (rect.right() - notes_left) / self.duration_sec.max(f32::EPSILON);
+        let time_to_x = |time_sec: f32| notes_left + (time_sec - self.offset_sec) * px_per_sec;
+
+        // Barlines every 4 beats
+        let end_sec = self.offset_sec + self.duration_sec;
+        for bar in self.bars() {
+            if (self.offset_sec..end_sec).contains(&bar) {
+                let x = time_to_x(bar);
+                painter.line_segment(
+                    [
+                        Pos2::new(x, layout.y(Clef::Treble, 10)),
+                        Pos2::new(x, layout.y(Clef::Bass, -10)),
+                    ],
+                    stroke,
+                );
+            }
+        }
+
+        // Chord symbols, skipping those starting before the visible range
+        for segment in self.chord_segments {
+            if let (Some(chord), true) = (
+                segment.chord,
+                (self.offset_sec..end_sec).contains(&segment.start_sec),
+            ) {
+                painter.text(
+                    Pos2::new(time_to_x(segment.start_sec), layout.y(Clef::Treble, 16)),
+                    egui::Align2::LEFT_BOTTOM,
+                    chord.symbol(self.key_signature < 0),
+                    egui::FontId::proportional(12.0),
+                    Color32::BLACK,
+                );
+            }
+        }
+
+        let chords = self.chords(time_to_x);
+        for chord in &chords {
+            draw_chord_heads(&painter, &layout, chord);
+        }
+        draw_stems_and_beams(&painter, &layout, &chords);
+    }
+
+    /// Groups visible notes into chords, sorted by time for each staff
+    fn chords(&self, time_to_x: impl Fn(f32) -> f32) -> Vec<Chord> {
+        let tick_sec = self.beat_sec.max(f32::EPSILON) / 4.0;
+        let end_sec = self.offset_sec + self.duration_sec;
+
+        let mut chords: Vec<Chord> = Vec::new();
+        for note in self.notes {
+            if note.start_sec < self.offset_sec || note.start_sec >= end_sec {
+                continue;
+            }
+            let position = staff_position(note.key, self.key_signature);
+            let tick = (note.start_sec / tick_sec).round() as i64;
+            let value = NoteValue::from_beats(note.duration_sec / self.beat_sec.max(f32::EPSILON));
+            let head = (position.step, position.accidental);
+
+            match chords
+                .iter_mut()
+                .find(|c| c.clef == position.clef && c.tick == tick)
+            {
+                Some(chord) => {
+                    chord.value = chord.value.max(value);
+                    chord.heads.push(head);
+                }
+                None => chords.push(Chord {
+                    clef: position.clef,
+                    x: time_to_x(tick as f32 * tick_sec),
+                    tick,
+                    value,
+                    heads: vec![head],
+                }),
+            }
+        }
+        chords.sort_by_key(|c| (c.clef == Clef::Bass, c.tick));
+        chords
+    }
+}
+
+/// Maps staff steps to screen coordinates
+struct Layout {
+    /// The y coordinate of the middle C between the staves
+    middle_c: f32,
+}
+
+impl Layout {
+    fn new(rect: Rect) -> Self {
+        Self {
+            middle_c: rect.center().y,
+        }
+    }
+
+    fn y(&self, clef: Clef, step: i32) -> f32 {
+        let gap = match clef {
+            Clef::Treble => -STAFF_GAP / 2.0,
+            Clef::Bass => STAFF_GAP / 2.0,
+        };
+        self.middle_c + gap - step as f32 * HALF_SPACE
+    }
+}
+
+fn draw_chord_heads(painter: &Painter, layout: &Layout, chord: &Chord) {
+    let stroke = Stroke::new(1.0, Color32::BLACK);
+    for (step, accidental) in &chord.heads {
+        let y = layout.y(chord.clef, *step);
+        for ledger in ledger_lines(chord.clef, *step) {
+            let ledger_y = layout.y(chord.clef, ledger);
+            let half_width = NOTEHEAD_SIZE.x + 3.0;
+            painter.line_segment(
+                [
+                    Pos2::new(chord.x - half_width, ledger_y),
+                    Pos2::new(chord.x + half_width, ledger_y),
+                ],
+                stroke,
+            );
+        }
+
+        let center = Pos2::new(chord.x, y);
+        if chord.value >= NoteValue::Half {
+            painter.add(Shape::ellipse_stroke(
+                center,
+                NOTEHEAD_SIZE,
+                Stroke::new(1.5, Color32::BLACK),
+            ));
+        } else {
+            painter.add(Shape::ellipse_filled(center, NOTEHEAD_SIZE, Color32::BLACK));
+        }
+
+        if let Some(accidental) = accidental {
+            draw_accidental(
+                painter,
+                *accidental,
+                Pos2::new(chord.x - 3.0 * NOTEHEAD_SIZE.x, y),
+            );
+        }
+    }
+}
+
+/// Draws stems for all chords except whole notes, connecting short notes within a beat with beams
+fn draw_stems_and_beams(painter: &Painter, layout: &Layout, chords: &[Chord]) {
+    let stroke = Stroke::new(1.0, Color32::BLACK);
+
+    // Split chords into groups that share a beam, each unbeamed chord is a group on its own
+    let mut groups: Vec<&[Chord]> = Vec::new();
+    let mut start = 0;
+    for i in 1..=chords.len() {
+        let split = i == chords.len() || {
+            let (prev, next) = (&chords[i - 1], &chords[i]);
+            prev.value.beams() == 0
+                || next.value.beams() == 0
+                || prev.clef != next.clef
+                || prev.tick / 4 != next.tick / 4
+        };
+        if split {
+            groups.push(&chords[start..i]);
+            start = i;
+        }
+    }
+
+    for group in groups {
+        let first = &group[0];
+        if first.value == NoteValue::Whole {
+            continue;
+        }
+
+        let steps = || {
+            group
+                .iter()
+                .flat_map(|c| c.heads.iter().map(|(step, _)| *step))
+        };
+        let stem_up = if group.len() == 1 {
+            first.stem_up()
+        } else {
+            steps()
+                .map(|step| step - first.clef.middle_line())
+                .sum::<i32>()
+                < 0
+        };
+
+        // All stems of the group end at the same height, far enough from every notehead
+        let (tip, stem_x_offset) = if stem_up {
+            let top = steps().max().unwrap_or_default();
+            (layout.y(first.clef, top) - STEM_LENGTH, NOTEHEAD_SIZE.x)
+        } else {
+            let bottom = steps().min().unwrap_or_default();
+            (layout.y(first.clef, bottom) + STEM_LENGTH, -NOTEHEAD_SIZE.x)
+        };
+
+        for chord in group {
+            let (low, high) = chord
+                .heads
+                .iter()
+                .fold((i32::MAX, i32::MIN), |(lo, hi), (step, _)| {
+                    (lo.min(*step), hi.max(*step))
+                });
+            let root = if stem_up { low } else { high };
+            let x = chord.x + stem_x_offset;
+            painter.line_segment(
+                [Pos2::new(x, layout.y(chord.clef, root)), Pos2::new(x, tip)],
+                stroke,
+            );
+        }
+
+        let beam_direction = if stem_up { 1.0 } else { -1.0 };
+        if group.len() == 1 {
+            // flags
+            let x = first.x + stem_x_offset;
+            for i in 0..first.value.beams() {
+                let y = tip + beam_direction * i as f32 * 1.5 * HALF_SPACE;
+                painter.add(QuadraticBezierShape::from_points_stroke(
+                    [
+                        Pos2::new(x, y),
+                        Pos2::new(x + 2.0 * HALF_SPACE, y + beam_direction * 2.0 * HALF_SPACE),
+                        Pos2::new(x + 1.5 * HALF_SPACE, y + beam_direction * 4.0 * HALF_SPACE),
+                    ],
+                    false,
+                    Color32::TRANSPARENT,
+                    Stroke::new(1.5, Color32::BLACK),
+                ));
+            }
+        } else {
+            for pair in group.windows(2) {
+                let beams = pair[0].value.beams().min(pair[1].value.beams());
+                for i in 0..beams {
+                    let y = tip + beam_direction * i as f32 * 1.5 * HALF_SPACE;
+                    painter.line_segment(
+                        [
+                            Pos2::new(pair[0].x + stem_x_offset, y),
+                            Pos2::new(pair[1].x + stem_x_offset, y),
+                        ],
+                        Stroke::new(3.0, Color32::BLACK),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn draw_accidental(painter: &Painter, accidental: Accidental, center: Pos2) {
+    let stroke = Stroke::new(1.0, Color32::BLACK);
+    let thick = Stroke::new(2.0, Color32::BLACK);
+    let (x, y) = (center.x, center.y);
+    match accidental {
+        Accidental::Sharp => {
+            for dx in [-1.5, 1.5] {
+                painter.line_segment(
+                    [Pos2::new(x + dx, y - 6.0), Pos2::new(x + dx, y + 6.0)],
+                    stroke,
+                );
+            }
+            for dy in [-2.5, 2.5] {
+                painter.line_segment(
+                    [
+                        Pos2::new(x - 3.5, y + dy + 1.0),
+                        Pos2::new(x + 3.5, y + dy - 1.0),
+                    ],
+                    thick,
+                );
+            }
+        }
+        Accidental::Flat => {
+            painter.line_segment(
+                [Pos2::new(x - 2.0, y - 9.0), Pos2::new(x - 2.0, y + 3.0)],
+                stroke,
+            );
+            painter.add(CubicBezierShape::from_points_stroke(
+                [
+                    Pos2::new(x - 2.0, y + 3.0),
+                    Pos2::new(x + 4.0, y - 1.0),
+                    Pos2::new(x + 3.0, y - 4.0),
+                    Pos2::new(x - 2.0, y - 1.0),
+                ],
+                false,
+                Color32::TRANSPARENT,
+                thick,
+            ));
+        }
+        Accidental::Natural => {
+            painter.line_segment(
+                [Pos2::new(x - 2.0, y - 6.0), Pos2::new(x - 2.0, y + 3.0)],
+                stroke,
+            );
+            painter.line_segment(
+                [Pos2::new(x + 2.0, y - 3.0), Pos2::new(x + 2.0, y + 6.0)],
+                stroke,
+            );
+            for dy in [-2.0, 2.0] {
+                painter.line_segment(
+                    [
+                        Pos2::new(x - 2.0, y + dy + 0.5),
+                        Pos2::new(x + 2.0, y + dy - 0.5),
+                    ],
+                    thick,
+                );
+            }
+        }
+    }
+}
+
+/// A simplified G clef: a spiral around the G4 line crossed by a vertical stroke with a curl at the bottom
+fn draw_treble_clef(painter: &Painter, layout: &Layout, left: f32) {
+    let stroke = Stroke::new(2.0, Color32::BLACK);
+    let x = left + 10.0;
+    let y = |step: f32| layout.y(Clef::Treble, 0) - step * HALF_SPACE;
+    let bezier = |points: [(f32, f32); 4]| {
+        CubicBezierShape::from_points_stroke(
+            points.map(|(dx, step)| Pos2::new(x + dx, y(step))),
+            false,
+            Color32::TRANSPARENT,
+            stroke,
+        )
+    };
+
+    // The spiral starting at G4 line
+    painter.add(bezier([(1.0, 4.0), (8.0, 4.5), (6.0, 1.0), (0.0, 1.5)]));
+    painter.add(bezier([(0.0, 1.5), (-9.0, 2.5), (-6.0, 8.0), (2.0, 10.0)]));
+    // The loop on top
+    painter.add(bezier([(2.0, 10.0), (8.0, 12.0), (6.0, 15.0), (2.0, 13.0)]));
+    // The vertical stroke with the curl at the bottom
+    painter.line_segment(
+        [Pos2::new(x + 2.0, y(13.0)), Pos2::new(x + 4.0, y(-1.0))],
+        stroke,
+    );
+    painter.add(bezier([
+        (4.0, -1.0),
+        (5.0, -3.0),
+        (-1.0, -3.0),
+        (-1.0, -1.0),
+    ]));
+    painter.circle_filled(Pos2::new(x, y(-1.0)), 2.0, Color32::BLACK);
+}
+
+/// A simplified F clef: a dot on the F3 line with an arc and two dots around the line
+fn draw_bass_clef(painter: &Painter, layout: &Layout, left: f32) {
+    let x = left + 4.0;
+    let f3 = layout.y(Clef::Bass, -4);
+    painter.circle_filled(Pos2::new(x + 2.0, f3), 2.5, Color32::BLACK);
+    painter.add(CubicBezierShape::from_points_stroke(
+        [
+            Pos2::new(x, f3),
+            Pos2::new(x + 4.0, f3 - 3.0 * HALF_SPACE),
+            Pos2::new(x + 20.0, f3 - 2.0 * HALF_SPACE),
+            Pos2::new(x + 1.0, f3 + 6.0 * HALF_SPACE),
+        ],
+        false,
+        Color32::TRANSPARENT,
+        Stroke::new(2.0, Color32::BLACK),
+    ));
+    for dy in [-HALF_SPACE, HALF_SPACE] {
+        painter.circle_filled(Pos2::new(x + 22.0, f3 + dy), 1.5, Color32::BLACK);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn staff_position_test() {
+        let position = |step, accidental| StaffPosition {
+            clef: if step >= 0 { Clef::Treble } else { Clef::Bass },
+            step,
+            accidental,
+        };
+
+        // C major
+        assert_eq!(staff_position(39, 0), position(0, None)); // C4
+        assert_eq!(staff_position(48, 0), position(5, None)); // A4
+        assert_eq!(staff_position(0, 0), position(-23, None)); // A0
+        assert_eq!(staff_position(87, 0), position(28, None)); // C8
+        assert_eq!(staff_position(38, 0), position(-1, None)); // B3
+        assert_eq!(staff_position(40, 0), position(0, Some(Accidental::Sharp))); // C#4
+
+        // G major has F#
+        assert_eq!(staff_position(45, 1), position(3, None)); // F#4
+        assert_eq!(
+            staff_position(44, 1),
+            position(3, Some(Accidental::Natural))
+        ); // F4
+
+        // F major has Bb
+        assert_eq!(staff_position(49, -1), position(6, None)); // Bb4
+        assert_eq!(
+            staff_position(50, -1),
+            position(6, Some(Accidental::Natural))
+        ); // B4
+        assert_eq!(staff_position(40, -1), position(1, Some(Accidental::Flat)));
+        // Db4
+    }
+
+    #[test]
+    fn ledger_lines_test() {
+        assert_eq!(ledger_lines(Clef::Treble, 6), vec![]);
+        assert_eq!(ledger_lines(Clef::Treble, 1), vec![]);
+        assert_eq!(ledger_lines(Clef::Treble, 0), vec![0]);
+        assert_eq!(ledger_lines(Clef::Treble, 11), vec![]);
+        assert_eq!(ledger_lines(Clef::Treble, 15), vec![12, 14]);
+        assert_eq!(ledger_lines(Clef::Bass, -1), vec![]);
+        assert_eq!(ledger_lines(Clef::Bass, -12), vec![-12]);
+        assert_eq!(ledger_lines(Clef::Bass, -15), vec![-14, -12]);
+    }
+
+    #[test]
+    fn note_value_test() {
+        assert_eq!(NoteValue::from_beats(4.0), NoteValue::Whole);
+        assert_eq!(NoteValue::from_beats(2.1), NoteValue::Half);
+        assert_eq!(NoteValue::from_beats(0.9), NoteValue::Quarter);
+        assert_eq!(NoteValue::from_beats(0.5), NoteValue::Eighth);
+        assert_eq!(NoteValue::from_beats(0.2), NoteValue::Sixteenth);
+    }
+
+    #[test]
+    fn bars_test() {
+        let beats = [0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 3.5, 4.0, 4.5];
+        let mut view = ScoreView {
+            notes: &[],
+            offset_sec: 3.0,
+            duration_sec: 10.0,
+            key_signature: 0,
+            beat_sec: 0.5,
+            beats: &beats,
+            chord_segments: &[],
+        };
+        assert_eq!(view.bars(), vec![0.5, 2.5, 4.5]);
+
+        // without tracked beats bars fall back to the fixed grid
+        view.beats = &[];
+        assert_eq!(view.bars(), vec![4.0, 6.0, 8.0, 10.0, 12.0]);
+    }
+}
@@ -0,0 +1,177 @@
+//! Short-time Fourier transform.
+
+use realfft::{num_complex::Complex, RealFftPlanner};
+
+use crate::{overlap_chunks::OverlapChunksExt, window_fn};
+
+/// Calculates spectra of frames of `window_size` samples windowed by the periodic Hann window and
+/// taken every `hop` samples. The incomplete last frame is dropped.
+pub(crate) fn stft(samples: &[f32], window_size: usize, hop: usize) -> Vec<Vec<Complex<f32>>> {
+    let mut planner = RealFftPlanner::<f32>::new();
+    let r2c = planner.plan_fft_forward(window_size);
+    let mut input_buf = r2c.make_input_vec();
+    let mut scratch_buf = r2c.make_scratch_vec();
+    let window = window(window_size);
+
+    samples
+        .overlap_chunks(window_size, window_size - hop)
+        .filter(|chunk| chunk.len() == window_size)
+        .map(|chunk| {
+            for ((input, sample), window) in input_buf.iter_mut().zip(chunk).zip(&window) {
+                *input = sample * window;
+            }
+            let mut output_buf = r2c.make_output_vec();
+            r2c.process_with_scratch(&mut input_buf, &mut output_buf, &mut scratch_buf)
+                .unwrap();
+            output_buf
+        })
+        .collect()
+}
+
+/// Inverse of `stft`: overlap-adds inverse transforms of frames weighted by the Hann window and
+/// divides the sum by the sum of squared windows, the least squares estimate of the signal.
+/// Frames are placed every `hop` samples as `stft` takes them, so unmodified spectra give back
+/// the original samples, except for the edges where the window is zero.
+/// https://doi.org/10.1109/TASSP.1984.1164317 (Griffin & Lim, 1984)
+pub(crate) fn istft(spectra: &[Vec<Complex<f32>>], window_size: usize, hop: usize) -> Vec<f32> {
+    let mut planner = RealFftPlanner::<f32>::new();
+    let c2r = planner.plan_fft_inverse(window_size);
+    let mut input_buf = c2r.make_input_vec();
+    let mut output_buf = c2r.make_output_vec();
+    let mut scratch_buf = c2r.make_scratch_vec();
+    let window = window(window_size);
+
+    let len = spectra.len().saturating_sub(1) * hop + window_size;
+    let mut samples = vec![0.0; len];
+    let mut weights = vec![0.0; len];
+    for (frame, spectrum) in spectra.iter().enumerate() {
+        input_buf.copy_from_slice(spectrum);
+        // imaginary parts of DC and Nyquist bins must be zero for the real output
+        input_buf[0].im = 0.0;
+        if let Some(nyquist) = input_buf
+            .last_mut()
+            .filter(|_| window_size.is_multiple_of(2))
+        {
+            nyquist.im = 0.0;
+        }
+        c2r.process_with_scratch(&mut input_buf, &mut output_buf, &mut scratch_buf)
+            .unwrap();
+
+        let offset = frame * hop;
+        for (i, (sample, window)) in output_buf.iter().zip(&window).enumerate() {
+            // realfft doesn't normalize the inverse transform
+            samples[offset + i] += sample / window_size as f32 * window;
+            weights[offset + i] += window * window;
+        }
+    }
+
+    for (sample, weight) in samples.iter_mut().zip(weights) {
+        *sample = if weight > 1e-6 { *sample / weight } else { 0.0 };
+    }
+    samples
+}
+
+/// The window of both transforms. The periodic form, unlike the symmetric one, is exactly COLA.
+fn window(window_size: usize) -> Vec<f32> {
+    window_fn::periodic(window_size, window_fn::hann)
+}
+
+/// Whether frames `hop` samples apart can be resynthesized after processing: the window applied by
+/// both transforms, i.e. its square, must be COLA, otherwise the normalization of `istft` varies
+/// from sample to sample and processed spectra are modulated by the frame rate
+pub(crate) fn is_resynthesizable(window_size: usize, hop: usize) -> bool {
+    let squared: Vec<f32> = window(window_size).iter().map(|w| w * w).collect();
+    window_fn::is_cola(&squared, hop)
+}
+
+/// Applies `process` to the spectrogram of samples and transforms it back to samples of the same
+/// length. Samples are padded with a window of silence on both sides, so that every sample is
+/// covered by all its frames. The framing must be resynthesizable, see [`is_resynthesizable`].
+pub(crate) fn filter(
+    samples: &[f32],
+    window_size: usize,
+    hop: usize,
+    process: impl FnOnce(&mut [Vec<Complex<f32>>]),
+) -> Vec<f32> {
+    debug_assert!(
+        is_resynthesizable(window_size, hop),
+        "{window_size} samples window with {hop} samples hop isn't COLA"
+    );
+    let frames = (samples.len() + window_size).div_ceil(hop) + 1;
+    let mut padded = vec![0.0; (frames - 1) * hop + window_size];
+    padded[window_size..window_size + samples.len()].copy_from_slice(samples);
+
+    let mut spectra = stft(&padded, window_size, hop);
+    process(&mut spectra);
+
+    let mut output = istft(&spectra, window_size, hop);
+    output.truncate(window_size + samples.len());
+    output.drain(..window_size);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stft_test() {
+        assert!(stft(&[], 16, 8).is_empty());
+        // the incomplete frame is dropped
+        assert!(stft(&[1.0; 15], 16, 8).is_empty());
+
+        // a sine wave exactly at the 4th bin
+        let window_size = 64;
+        let samples: Vec<f32> = (0..window_size * 4)
+            .map(|i| (2.0 * std::f32::consts::PI * 4.0 * i as f32 / window_size as f32).sin())
+            .collect();
+        let spectra = stft(&samples, window_size, window_size / 2);
+        assert_eq!(spectra.len(), 7);
+        for spectrum in spectra {
+            assert_eq!(spectrum.len(), window_size / 2 + 1);
+            let peak = spectrum
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.norm().total_cmp(&b.1.norm()))
+                .unwrap()
+                .0;
+            assert_eq!(peak, 4);
+        }
+    }
+
+    #[test]
+    fn is_resynthesizable_test() {
+        // the squared Hann window needs at least 75% overlap
+        assert!(is_resynthesizable(64, 16));
+        assert!(is_resynthesizable(4096, 1024));
+        assert!(is_resynthesizable(64, 8));
+        assert!(!is_resynthesizable(64, 32));
+        assert!(!is_resynthesizable(64, 0));
+    }
+
+    #[test]
+    fn istft_test() {
+        assert_eq!(istft(&[], 16, 8), vec![0.0; 16]);
+
+        let samples: Vec<f32> = (0..1000)
+            .map(|i| (i as f32 * 0.05).sin() + 0.3 * (i as f32 * 0.7).cos())
+            .collect();
+        for (window_size, hop) in [(64, 32), (128, 64), (64, 16)] {
+            let restored = istft(&stft(&samples, window_size, hop), window_size, hop);
+            // the tail that doesn't fill the last frame is dropped by `stft`
+            let frames = (samples.len() - window_size) / hop + 1;
+            assert_eq!(restored.len(), (frames - 1) * hop + window_size);
+            // a few samples at the edges are only under the near zero tails of the window
+            let edge = window_size / 16;
+            for i in edge..restored.len() - edge {
+                assert!(
+                    (restored[i] - samples[i]).abs() < 1e-3,
+                    "{window_size} {hop} {i}: {} != {}",
+                    restored[i],
+                    samples[i]
+                );
+            }
+            assert_eq!(restored[0], 0.0);
+        }
+    }
+}
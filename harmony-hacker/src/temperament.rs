@@ -0,0 +1,212 @@
+//! Tuning systems that map piano keys to frequencies.
+//! https://en.wikipedia.org/wiki/Musical_temperament
+//!
+//! Historical temperaments are defined by the pitches of 12 notes within the octave. Regardless of
+//! the temperament, A4 sounds at the reference frequency, so the reference estimation and the
+//! slider keep working the same way.
+
+use std::fmt;
+
+use crate::{
+    chroma,
+    scala::{self, KeyboardMapping, Scale},
+    tuning,
+};
+
+/// The pure fifth 3/2 in cents
+const PURE_FIFTH: f32 = 701.955;
+/// The fifth narrowed by a quarter of the syntonic comma to get pure major thirds
+const MEANTONE_FIFTH: f32 = 696.578;
+/// Werckmeister III ("correct temperament no. 1"), pitches starting from C in cents
+const WERCKMEISTER_III: [f32; 12] = [
+    0.0, 90.225, 192.18, 294.135, 390.225, 498.045, 588.27, 696.09, 792.18, 888.27, 996.09, 1092.18,
+];
+/// 5-limit just intonation, ratios of the chromatic scale degrees to the tonic
+const JUST_RATIOS: [(u32, u32); 12] = [
+    (1, 1),
+    (16, 15),
+    (9, 8),
+    (6, 5),
+    (5, 4),
+    (4, 3),
+    (45, 32),
+    (3, 2),
+    (8, 5),
+    (5, 3),
+    (9, 5),
+    (15, 8),
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) enum Temperament {
+    #[default]
+    Equal,
+    /// Pure fifths from Eb to G#, with the wolf fifth between G# and Eb
+    Pythagorean,
+    /// Fifths from Eb to G# narrowed to get pure major thirds
+    QuarterCommaMeantone,
+    WerckmeisterIII,
+    /// 5-limit just intonation built on the tonic pitch class (0 is C)
+    Just {
+        tonic: usize,
+    },
+    /// A scale loaded from a Scala file
+    Scala {
+        scale: Scale,
+        mapping: KeyboardMapping,
+    },
+}
+
+impl Temperament {
+    /// The frequency of the key number 0..88, where the key 48 (A4) sounds at `reference_hz`
+    pub(crate) fn key_frequency(&self, key: u8, reference_hz: f32) -> f32 {
+        match self {
+            Temperament::Equal => tuning::key_frequency(key, reference_hz),
+            _ => reference_hz * 2.0f32.powf(self.cents_from_a4(key as i32) / 1200.0),
+        }
+    }
+
+    /// The frequency of A4 that makes the reference note of the Scala keyboard mapping sound at
+    /// its reference frequency. `None` for other temperaments.
+    pub(crate) fn scala_reference_hz(&self) -> Option<f32> {
+        let Temperament::Scala { mapping, .. } = self else {
+            return None;
+        };
+        let cents = self.cents_from_a4(mapping.reference_note - scala::MIDI_A0);
+        Some(mapping.reference_hz * 2.0f32.powf(-cents / 1200.0))
+    }
+
+    /// The pitch of the key relative to A4 in cents, the key may be outside of the keyboard
+    fn cents_from_a4(&self, key: i32) -> f32 {
+        let equal = (key - 48) as f32 * 100.0;
+        let (pitches, tonic) = match self {
+            Temperament::Equal => return equal,
+            Temperament::Pythagorean => (fifths_chain(PURE_FIFTH), 0),
+            Temperament::QuarterCommaMeantone => (fifths_chain(MEANTONE_FIFTH), 0),
+            Temperament::WerckmeisterIII => (WERCKMEISTER_III, 0),
+            Temperament::Just { tonic } => (
+                JUST_RATIOS.map(|(numerator, denominator)| {
+                    1200.0 * (numerator as f32 / denominator as f32).log2()
+                }),
+                *tonic,
+            ),
+            Temperament::Scala { scale, mapping } => {
+                // Unmapped keys fall back to the equal temperament
+                let degree = |key| mapping.degree(key + scala::MIDI_A0, scale);
+                return match (degree(key), degree(48)) {
+                    (Some(degree), Some(a4)) => scale.cents(degree) - scale.cents(a4),
+                    _ => equal,
+                };
+            }
+        };
+
+        // Semitones above the tonic in the octave below A0
+        let cents = |key: i32| {
+            let semitones = key + 9 - tonic as i32;
+            semitones.div_euclid(12) as f32 * 1200.0 + pitches[semitones.rem_euclid(12) as usize]
+        };
+        cents(key) - cents(48)
+    }
+}
+
+impl fmt::Display for Temperament {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Temperament::Equal => write!(f, "Equal"),
+            Temperament::Pythagorean => write!(f, "Pythagorean"),
+            Temperament::QuarterCommaMeantone => write!(f, "1/4-comma meantone"),
+            Temperament::WerckmeisterIII => write!(f, "Werckmeister III"),
+            Temperament::Just { tonic } => {
+                write!(f, "Just in {}", chroma::pitch_class_name(*tonic, false))
+            }
+            Temperament::Scala { scale, .. } => write!(f, "Scala: {}", scale.description),
+        }
+    }
+}
+
+/// Pitches of 12 notes starting from C tuned by the chain of fifths from Eb to G#
+fn fifths_chain(fifth: f32) -> [f32; 12] {
+    let mut pitches = [0.0; 12];
+    for fifths in -3..=8i32 {
+        pitches[(fifths * 7).rem_euclid(12) as usize] = (fifths as f32 * fifth).rem_euclid(1200.0);
+    }
+    pitches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The interval between two keys in cents
+    fn interval(temperament: &Temperament, from: u8, to: u8) -> f32 {
+        let from = temperament.key_frequency(from, 440.0);
+        let to = temperament.key_frequency(to, 440.0);
+        1200.0 * (to / from).log2()
+    }
+
+    #[test]
+    fn key_frequency_test() {
+        let temperaments = [
+            Temperament::Equal,
+            Temperament::Pythagorean,
+            Temperament::QuarterCommaMeantone,
+            Temperament::WerckmeisterIII,
+            Temperament::Just { tonic: 2 },
+            Temperament::Scala {
+                scale: Scale::parse("12-EDO\n12\n100.0\n200.0\n300.0\n400.0\n500.0\n600.0\n700.0\n800.0\n900.0\n1000.0\n1100.0\n2/1\n").unwrap(),
+                mapping: KeyboardMapping::default(),
+            },
+        ];
+        for temperament in &temperaments {
+            // A4 is the reference and octaves are pure
+            assert_eq!(temperament.key_frequency(48, 442.0), 442.0);
+            for key in 0..76 {
+                assert!(
+                    (interval(temperament, key, key + 12) - 1200.0).abs() < 1e-2,
+                    "{temperament} {key}"
+                );
+            }
+        }
+
+        // C4 - G4, C4 - E4, the wolf fifth G#4 - Eb5 and pure A4 - E5 in Werckmeister III
+        assert!((interval(&Temperament::Equal, 39, 46) - 700.0).abs() < 1e-2);
+        assert!((interval(&Temperament::Pythagorean, 39, 46) - PURE_FIFTH).abs() < 1e-2);
+        assert!((interval(&Temperament::Pythagorean, 39, 43) - 407.82).abs() < 1e-2);
+        assert!((interval(&Temperament::Pythagorean, 47, 54) - 678.49).abs() < 1e-2);
+        let meantone = Temperament::QuarterCommaMeantone;
+        assert!((interval(&meantone, 39, 43) - 386.31).abs() < 1e-2);
+        assert!((interval(&meantone, 41, 45) - 386.31).abs() < 1e-2);
+        assert!((interval(&Temperament::WerckmeisterIII, 39, 46) - 696.09).abs() < 1e-2);
+        assert!((interval(&Temperament::WerckmeisterIII, 48, 55) - 701.96).abs() < 1e-2);
+
+        // D major triad is pure in just intonation on D, but not on C
+        let just_d = Temperament::Just { tonic: 2 };
+        assert!((interval(&just_d, 41, 45) - 386.31).abs() < 1e-2);
+        assert!((interval(&just_d, 41, 48) - PURE_FIFTH).abs() < 1e-2);
+        let just_c = Temperament::Just { tonic: 0 };
+        assert!((interval(&just_c, 41, 48) - 680.45).abs() < 1e-2);
+    }
+
+    #[test]
+    fn scala_reference_test() {
+        // The pentatonic scale mapped linearly from C4, so C4 sounds at the reference 261 Hz
+        let temperament = Temperament::Scala {
+            scale: Scale::parse("pentatonic\n5\n9/8\n5/4\n3/2\n5/3\n2/1\n").unwrap(),
+            mapping: KeyboardMapping {
+                reference_note: 60,
+                reference_hz: 261.0,
+                ..Default::default()
+            },
+        };
+        assert_eq!(Temperament::Equal.scala_reference_hz(), None);
+        // A4 (MIDI 69) is the 9th degree, i.e. 5/3 in the next period
+        let reference_hz = temperament.scala_reference_hz().unwrap();
+        assert!((reference_hz - 870.0).abs() < 1e-2);
+
+        let frequency = |key| temperament.key_frequency(key, reference_hz);
+        assert!((frequency(39) - 261.0).abs() < 1e-2);
+        assert!((frequency(40) - 293.625).abs() < 1e-2);
+        assert!((frequency(43) - 435.0).abs() < 1e-2);
+        assert!((frequency(44) - 522.0).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,163 @@
+//! Musical key estimation with the Krumhansl-Schmuckler algorithm.
+//! http://rnhart.net/articles/key-finding/
+//!
+//! The chroma of the analysed range is correlated with the major and minor key profiles
+//! rotated to each of 12 tonics, and the best matching one wins.
+
+use std::fmt;
+
+use crate::chroma::{self, Chroma};
+
+/// Krumhansl-Kessler probe tone ratings for the major key, starting from the tonic
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+/// Krumhansl-Kessler probe tone ratings for the minor key, starting from the tonic
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Key {
+    /// Pitch class of the tonic, 0 is C
+    pub(crate) tonic: usize,
+    pub(crate) mode: Mode,
+}
+
+impl Key {
+    /// Number of sharps (positive) or flats (negative) in the key signature
+    pub(crate) fn key_signature(&self) -> i8 {
+        // Minor keys share the signature with the relative major a minor third above
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+        // Each step on the circle of fifths adds a sharp, prefer flats after 6 steps (F# vs Gb)
+        let fifths = (major_tonic * 7 % 12) as i8;
+        if fifths > 6 {
+            fifths - 12
+        } else {
+            fifths
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tonic = chroma::pitch_class_name(self.tonic, self.key_signature() < 0);
+        let mode = match self.mode {
+            Mode::Major => "major",
+            Mode::Minor => "minor",
+        };
+        write!(f, "{tonic} {mode}")
+    }
+}
+
+/// The estimated key with the Pearson correlation between the chroma and the key profile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct KeyEstimate {
+    pub(crate) key: Key,
+    /// Correlation in -1..1 range, the higher the more confident the estimate
+    pub(crate) confidence: f32,
+}
+
+/// Estimates the key of the chroma. Returns `None` for the silence or flat chroma.
+pub(crate) fn estimate_key(chroma: &Chroma) -> Option<KeyEstimate> {
+    (0..12)
+        .flat_map(|tonic| {
+            [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)].map(|(mode, profile)| {
+                // the profile starts from the tonic, so rotate it to start from C
+                let rotated: [f32; 12] = std::array::from_fn(|i| profile[(i + 12 - tonic) % 12]);
+                KeyEstimate {
+                    key: Key { tonic, mode },
+                    confidence: correlation(chroma, &rotated),
+                }
+            })
+        })
+        .filter(|estimate| estimate.confidence.is_finite())
+        .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+}
+
+/// Pearson correlation coefficient, NaN if any of the inputs is constant
+fn correlation(a: &[f32; 12], b: &[f32; 12]) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / 12.0;
+    let mean_b = b.iter().sum::<f32>() / 12.0;
+    let (mut covariance, mut variance_a, mut variance_b) = (0.0, 0.0, 0.0);
+    for (a, b) in a.iter().zip(b) {
+        covariance += (a - mean_a) * (b - mean_b);
+        variance_a += (a - mean_a) * (a - mean_a);
+        variance_b += (b - mean_b) * (b - mean_b);
+    }
+    covariance / (variance_a * variance_b).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn key_signature_test() {
+        let key = |tonic, mode| Key { tonic, mode };
+        assert_eq!(key(0, Mode::Major).key_signature(), 0);
+        assert_eq!(key(9, Mode::Minor).key_signature(), 0);
+        assert_eq!(key(7, Mode::Major).key_signature(), 1);
+        assert_eq!(key(4, Mode::Minor).key_signature(), 1);
+        assert_eq!(key(5, Mode::Major).key_signature(), -1);
+        assert_eq!(key(2, Mode::Minor).key_signature(), -1);
+        assert_eq!(key(6, Mode::Major).key_signature(), 6);
+        assert_eq!(key(1, Mode::Major).key_signature(), -5);
+        assert_eq!(key(3, Mode::Minor).key_signature(), 6);
+
+        assert_eq!(key(0, Mode::Major).to_string(), "C major");
+        assert_eq!(key(10, Mode::Major).to_string(), "Bb major");
+        assert_eq!(key(1, Mode::Minor).to_string(), "C# minor");
+    }
+
+    #[test]
+    fn estimate_key_test() {
+        assert_eq!(estimate_key(&[0.0; 12]), None);
+
+        // Notes of the scale with the tonic triad emphasized
+        let scale = |tonic: usize, steps: [usize; 7]| {
+            let mut chroma = [0.0; 12];
+            for step in steps {
+                chroma[(tonic + step) % 12] += 1.0;
+            }
+            for step in [steps[0], steps[2], steps[4]] {
+                chroma[(tonic + step) % 12] += 1.0;
+            }
+            chroma
+        };
+        let major = [0, 2, 4, 5, 7, 9, 11];
+        let minor = [0, 2, 3, 5, 7, 8, 10];
+
+        for tonic in 0..12 {
+            let estimate = estimate_key(&scale(tonic, major)).unwrap();
+            assert_eq!(
+                estimate.key,
+                Key {
+                    tonic,
+                    mode: Mode::Major
+                }
+            );
+            assert!(estimate.confidence > 0.8);
+
+            let estimate = estimate_key(&scale(tonic, minor)).unwrap();
+            assert_eq!(
+                estimate.key,
+                Key {
+                    tonic,
+                    mode: Mode::Minor
+                }
+            );
+            assert!(estimate.confidence > 0.8);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Tuning reference (the frequency of A4) and its estimation from a recording.
+//!
+//! Pianos are often tuned slightly off the standard 440 Hz, e.g. to 442 Hz, so every key is shifted
+//! by the same number of cents. Spectral peaks of such a recording deviate from the equal tempered
+//! pitches by that amount, so the most common deviation gives the reference.
+
+use crate::stft;
+
+/// The standard frequency of A4
+pub(crate) const STANDARD_REFERENCE_HZ: f32 = 440.0;
+
+/// The duration of the analysis window, long enough to resolve the lowest keys
+const WINDOW_SEC: f32 = 0.17;
+/// Peaks weaker than the strongest one in the frame by this ratio (-40 dB) are ignored
+const MIN_PEAK_RATIO: f32 = 0.01;
+/// The frequency range of peaks taken into account, from A0 to C8
+const MIN_PEAK_HZ: f32 = 27.5;
+const MAX_PEAK_HZ: f32 = 4186.01;
+
+/// The frequency of the key number 0..88 in the equal temperament, where the key 48 is A4
+pub(crate) fn key_frequency(key: u8, reference_hz: f32) -> f32 {
+    reference_hz * 2.0f32.powf((key as f32 - 48.0) / 12.0)
+}
+
+/// Estimates the frequency of A4 within a quarter-tone of `around_hz`.
+///
+/// The deviation of a pitch is only known modulo a semitone, so a recording tuned to 415 Hz
+/// looks like one tuned to 440 Hz with every note a semitone lower. `around_hz` resolves it.
+/// Returns `None` if there are no spectral peaks in the signal.
+pub(crate) fn estimate_reference(samples: &[f32], sample_rate: u32, around_hz: f32) -> Option<f32> {
+    let window_size = ((sample_rate as f32 * WINDOW_SEC) as usize).next_power_of_two();
+    let bin_hz = sample_rate as f32 / window_size as f32;
+
+    // Histogram of deviations with 1 cent bins, weighted by the peak magnitude
+    let mut histogram = [0.0f32; 100];
+    for spectrum in stft::stft(samples, window_size, window_size / 2) {
+        let magnitudes: Vec<f32> = spectrum.iter().map(|bin| bin.norm()).collect();
+        let max = magnitudes.iter().copied().fold(0.0, f32::max);
+        for k in 1..magnitudes.len().saturating_sub(1) {
+            let (prev, peak, next) = (magnitudes[k - 1], magnitudes[k], magnitudes[k + 1]);
+            if peak <= prev || peak < next || peak < max * MIN_PEAK_RATIO || peak == 0.0 {
+                continue;
+            }
+
+            // Parabolic interpolation of the log magnitude for the exact peak position
+            let (prev, peak_log, next) = (prev.max(1e-10).ln(), peak.ln(), next.max(1e-10).ln());
+            let denominator = prev - 2.0 * peak_log + next;
+            let shift = if denominator < 0.0 {
+                0.5 * (prev - next) / denominator
+            } else {
+                0.0
+            };
+            let frequency = (k as f32 + shift) * bin_hz;
+            if !(MIN_PEAK_HZ..=MAX_PEAK_HZ).contains(&frequency) {
+                continue;
+            }
+
+            let cents = 1200.0 * (frequency / around_hz).log2();
+            let deviation = cents - 100.0 * (cents / 100.0).round();
+            let bin = (deviation + 50.0).floor() as usize % histogram.len();
+            histogram[bin] += peak;
+        }
+    }
+
+    // Smooth the histogram (it wraps around) and take the most common deviation
+    let smoothed: Vec<f32> = (0..histogram.len())
+        .map(|i| {
+            (-2..=2)
+                .map(|offset: isize| {
+                    let j = (i as isize + offset).rem_euclid(histogram.len() as isize) as usize;
+                    histogram[j] * (3 - offset.abs()) as f32
+                })
+                .sum()
+        })
+        .collect();
+    let (best, weight) = smoothed
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    if *weight == 0.0 {
+        return None;
+    }
+
+    let deviation = best as f32 + 0.5 - 50.0;
+    Some(around_hz * 2.0f32.powf(deviation / 1200.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_frequency_test() {
+        assert_eq!(key_frequency(48, 440.0), 440.0);
+        assert_eq!(key_frequency(36, 440.0), 220.0);
+        assert_eq!(key_frequency(0, 440.0), 27.5);
+        assert!((key_frequency(39, 440.0) - 261.6256).abs() < 1e-3);
+        assert!((key_frequency(87, 440.0) - 4186.009).abs() < 1e-2);
+        assert_eq!(key_frequency(48, 442.0), 442.0);
+    }
+
+    #[test]
+    fn estimate_reference_test() {
+        let sample_rate = 16000;
+        // C major chord with overtones in the piano tuned to the given reference
+        let chord = |reference_hz: f32| -> Vec<f32> {
+            let frequencies: Vec<f32> = [27, 39, 43, 46]
+                .into_iter()
+                .map(|key| key_frequency(key, reference_hz))
+                .collect();
+            (0..sample_rate * 2)
+                .map(|i| {
+                    let t = i as f32 / sample_rate as f32;
+                    frequencies
+                        .iter()
+                        .flat_map(|f| (1..=3).map(move |n| (n as f32, f * n as f32)))
+                        .map(|(n, f)| (2.0 * std::f32::consts::PI * f * t).sin() / n)
+                        .sum()
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            estimate_reference(&vec![0.0; 16000], sample_rate, 440.0),
+            None
+        );
+
+        for reference_hz in [440.0, 442.0, 436.0, 445.0] {
+            let estimated = estimate_reference(&chord(reference_hz), sample_rate, 440.0).unwrap();
+            assert!(
+                (estimated - reference_hz).abs() < 0.5,
+                "{estimated} vs {reference_hz}"
+            );
+        }
+
+        // Baroque pitch is found only around the expected reference
+        let estimated = estimate_reference(&chord(415.0), sample_rate, 415.0).unwrap();
+        assert!((estimated - 415.0).abs() < 0.5, "{estimated}");
+    }
+}
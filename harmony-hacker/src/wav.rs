@@ -0,0 +1,59 @@
+//! A minimal WAV writer for exporting resynthesized audio.
+//! http://soundfile.sapp.org/doc/WaveFormat/
+
+use std::io::Write;
+
+/// Writes samples in -1..1 as a mono 16-bit PCM WAV file, samples out of range are clipped
+pub(crate) fn write_wav(
+    mut writer: impl Write,
+    samples: &[f32],
+    sample_rate: u32,
+) -> std::io::Result<()> {
+    const BYTES_PER_SAMPLE: u32 = 2;
+    let data_size = samples.len() as u32 * BYTES_PER_SAMPLE;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * BYTES_PER_SAMPLE).to_le_bytes())?;
+    writer.write_all(&(BYTES_PER_SAMPLE as u16).to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn write_wav_test() {
+        let mut output = Vec::new();
+        write_wav(&mut output, &[0.0, 1.0, -1.0, 2.0], 8000).unwrap();
+        assert_eq!(output.len(), 44 + 8);
+        assert_eq!(&output[..4], b"RIFF");
+        assert_eq!(&output[4..8], &44u32.to_le_bytes());
+        assert_eq!(&output[8..16], b"WAVEfmt ");
+        assert_eq!(&output[24..28], &8000u32.to_le_bytes());
+        assert_eq!(&output[36..40], b"data");
+        assert_eq!(&output[40..44], &8u32.to_le_bytes());
+        assert_eq!(
+            &output[44..],
+            &[0x00, 0x00, 0xff, 0x7f, 0x01, 0x80, 0xff, 0x7f]
+        );
+    }
+}
@@ -1,3 +1,32 @@
+//! Window functions for spectral analysis. All of them are symmetric: the first and the last
+//! samples are at the same distance from the center, `periodic` turns them into DFT-even ones.
+//! https://en.wikipedia.org/wiki/Window_function
+//!
+//! Windows attenuate the signal, so magnitudes depend on the window unless it's normalized by its
+//! coherent gain (for amplitudes of sines) or by its RMS (for the energy of noise).
+//! https://holometer.fnal.gov/GH_FFT.pdf (Heinzel, Rüdiger & Schilling, 2002)
+
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Symmetry {
+    /// For filter design and analysis of single frames
+    #[default]
+    Symmetric,
+    /// For spectral analysis, the period of the window is exactly the DFT length
+    Periodic,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Normalization {
+    None,
+    /// Sines have the same magnitude as with the rectangular window
+    #[default]
+    Amplitude,
+    /// Noise has the same power as with the rectangular window
+    Energy,
+}
+
 /// Calculates the Hann window function for the given sample count
 /// https://en.wikipedia.org/wiki/Hann_function
 pub(crate) fn hann(sample_count: usize) -> Vec<f32> {
@@ -25,6 +54,190 @@ pub(crate) fn hann(sample_count: usize) -> Vec<f32> {
     window
 }
 
+/// Calculates the derivative of the Hann window by the sample index
+pub(crate) fn hann_derivative(sample_count: usize) -> Vec<f32> {
+    if sample_count < 2 {
+        return vec![0.0; sample_count];
+    }
+    let scale = 2.0 * PI / (sample_count - 1) as f32;
+    (0..sample_count)
+        .map(|i| 0.5 * scale * (scale * i as f32).sin())
+        .collect()
+}
+
+/// Calculates the Hamming window function, which cancels the first side lobe of the Hann window
+pub(crate) fn hamming(sample_count: usize) -> Vec<f32> {
+    cosine_sum(sample_count, &[0.54, 0.46])
+}
+
+/// Calculates the Blackman window function
+pub(crate) fn blackman(sample_count: usize) -> Vec<f32> {
+    cosine_sum(sample_count, &[0.42, 0.5, 0.08])
+}
+
+/// Calculates the 4-term Blackman-Harris window function with side lobes below -92 dB
+pub(crate) fn blackman_harris(sample_count: usize) -> Vec<f32> {
+    cosine_sum(sample_count, &[0.35875, 0.48829, 0.14128, 0.01168])
+}
+
+/// Calculates the 4-term Nuttall window function with continuous first derivative
+pub(crate) fn nuttall(sample_count: usize) -> Vec<f32> {
+    cosine_sum(sample_count, &[0.355768, 0.487396, 0.144232, 0.012604])
+}
+
+/// Calculates the flat-top window function, which has the smallest amplitude error for
+/// frequencies between bins at the cost of the widest main lobe
+pub(crate) fn flat_top(sample_count: usize) -> Vec<f32> {
+    cosine_sum(
+        sample_count,
+        &[0.21557895, 0.41663158, 0.27726316, 0.083578947, 0.006947368],
+    )
+}
+
+/// Calculates the Kaiser window function, `beta` trades the main lobe width for the side lobe level
+/// https://en.wikipedia.org/wiki/Kaiser_window
+pub(crate) fn kaiser(sample_count: usize, beta: f32) -> Vec<f32> {
+    let denominator = bessel_i0(beta);
+    symmetric(sample_count, |x| {
+        let t = 2.0 * x - 1.0;
+        bessel_i0(beta * (1.0 - t * t).max(0.0).sqrt()) / denominator
+    })
+}
+
+/// Calculates the Gaussian window function with `sigma` relative to the half of the window
+pub(crate) fn gaussian(sample_count: usize, sigma: f32) -> Vec<f32> {
+    symmetric(sample_count, |x| {
+        let t = (2.0 * x - 1.0) / sigma;
+        (-0.5 * t * t).exp()
+    })
+}
+
+/// Calculates the Tukey (tapered cosine) window function with the `alpha` fraction of the window
+/// tapered, from the rectangular window at 0 to the Hann window at 1
+pub(crate) fn tukey(sample_count: usize, alpha: f32) -> Vec<f32> {
+    symmetric(sample_count, |x| {
+        // distance from the closest edge in units of the taper length
+        let edge = x.min(1.0 - x) * 2.0 / alpha;
+        if alpha <= 0.0 || edge >= 1.0 {
+            1.0
+        } else {
+            0.5 - 0.5 * (PI * edge).cos()
+        }
+    })
+}
+
+/// Calculates the Bartlett (triangular) window function with zeros at both ends
+pub(crate) fn bartlett(sample_count: usize) -> Vec<f32> {
+    symmetric(sample_count, |x| 1.0 - (2.0 * x - 1.0).abs())
+}
+
+/// Calculates the Welch (parabolic) window function
+pub(crate) fn welch(sample_count: usize) -> Vec<f32> {
+    symmetric(sample_count, |x| {
+        let t = 2.0 * x - 1.0;
+        1.0 - t * t
+    })
+}
+
+/// Calculates the periodic form of the symmetric window, which is the window one sample longer
+/// without the last sample
+pub(crate) fn periodic(sample_count: usize, window_fn: impl Fn(usize) -> Vec<f32>) -> Vec<f32> {
+    let mut window = window_fn(sample_count + 1);
+    window.truncate(sample_count);
+    window
+}
+
+/// The mean of the window, the factor sines are attenuated by
+pub(crate) fn coherent_gain(window: &[f32]) -> f32 {
+    window.iter().sum::<f32>() / window.len() as f32
+}
+
+/// The equivalent noise bandwidth of the window in bins: the width of the rectangular filter that
+/// passes the same noise power
+pub(crate) fn enbw(window: &[f32]) -> f32 {
+    let sum: f32 = window.iter().sum();
+    let sum_squared: f32 = window.iter().map(|w| w * w).sum();
+    window.len() as f32 * sum_squared / (sum * sum)
+}
+
+/// Whether overlapped copies of the window shifted by `hop` sum up to a constant, the constant
+/// overlap-add property needed to resynthesize the signal from its frames unchanged
+pub(crate) fn is_cola(window: &[f32], hop: usize) -> bool {
+    if hop == 0 || hop > window.len() {
+        return false;
+    }
+    let sums: Vec<f32> = (0..hop)
+        .map(|offset| window.iter().skip(offset).step_by(hop).sum())
+        .collect();
+    let max = sums.iter().copied().fold(f32::MIN, f32::max);
+    let min = sums.iter().copied().fold(f32::MAX, f32::min);
+    min > 0.0 && max / min - 1.0 < 1e-3
+}
+
+/// Scales the window so magnitudes are comparable with the rectangular window
+pub(crate) fn normalize(mut window: Vec<f32>, normalization: Normalization) -> Vec<f32> {
+    let gain = match normalization {
+        Normalization::None => return window,
+        Normalization::Amplitude => coherent_gain(&window),
+        Normalization::Energy => {
+            (window.iter().map(|w| w * w).sum::<f32>() / window.len() as f32).sqrt()
+        }
+    };
+    if gain > 0.0 {
+        for value in &mut window {
+            *value /= gain;
+        }
+    }
+    window
+}
+
+/// Generalized cosine window `sum((-1)^k * a_k * cos(2 * pi * k * x))`
+fn cosine_sum(sample_count: usize, coefficients: &[f32]) -> Vec<f32> {
+    symmetric(sample_count, |x| {
+        coefficients
+            .iter()
+            .enumerate()
+            .map(|(k, a)| {
+                let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                sign * a * (2.0 * PI * k as f32 * x).cos()
+            })
+            .sum()
+    })
+}
+
+/// Calculates a symmetric window from its shape over 0..=1, like in `hann` only the first half
+/// is calculated. A single sample window is the value at the edge.
+fn symmetric(sample_count: usize, shape: impl Fn(f32) -> f32) -> Vec<f32> {
+    match sample_count {
+        0 => return vec![],
+        1 => return vec![shape(0.0)],
+        _ => (),
+    }
+
+    let mut window: Vec<f32> = (0..sample_count.div_ceil(2))
+        .map(|i| shape(i as f32 / (sample_count - 1) as f32))
+        .collect();
+    for i in window.len()..sample_count {
+        window.push(window[sample_count - i - 1]);
+    }
+    window
+}
+
+/// The zeroth order modified Bessel function of the first kind, by its power series
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_squared = x * x / 4.0;
+    for k in 1..50 {
+        term *= half_squared / (k * k) as f32;
+        sum += term;
+        if term < sum * 1e-9 {
+            break;
+        }
+    }
+    sum
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -55,4 +268,151 @@ mod tests {
             assert!((value - 1.0).abs() < 1e-2);
         }
     }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < 1e-4, "{i}: {actual:?} != {expected:?}");
+        }
+    }
+
+    fn assert_symmetric(name: &str, window_fn: impl Fn(usize) -> Vec<f32>) {
+        assert_eq!(window_fn(0), vec![], "{name}");
+        for size in [1, 2, 7, 64, 1023] {
+            let window = window_fn(size);
+            assert_eq!(window.len(), size, "{name}");
+            for i in 0..size {
+                assert_eq!(window[i], window[size - i - 1], "{name} {size} {i}");
+            }
+        }
+        // every window peaks at 1 in the center
+        let window = window_fn(1023);
+        assert!((window[511] - 1.0).abs() < 2e-3, "{name} {}", window[511]);
+    }
+
+    #[test]
+    fn symmetry_test() {
+        assert_symmetric("hann", hann);
+        assert_symmetric("hamming", hamming);
+        assert_symmetric("blackman", blackman);
+        assert_symmetric("blackman_harris", blackman_harris);
+        assert_symmetric("nuttall", nuttall);
+        assert_symmetric("flat_top", flat_top);
+        assert_symmetric("kaiser", |n| kaiser(n, 8.6));
+        assert_symmetric("gaussian", |n| gaussian(n, 0.4));
+        assert_symmetric("tukey", |n| tukey(n, 0.5));
+        assert_symmetric("rectangular tukey", |n| tukey(n, 0.0));
+        assert_symmetric("bartlett", bartlett);
+        assert_symmetric("welch", welch);
+    }
+
+    #[test]
+    fn hann_derivative_test() {
+        assert_eq!(hann_derivative(1), vec![0.0]);
+        let window = hann(1024);
+        let derivative = hann_derivative(1024);
+        for i in 1..1023 {
+            let difference = (window[i + 1] - window[i - 1]) / 2.0;
+            assert!((derivative[i] - difference).abs() < 1e-5, "{i}");
+        }
+    }
+
+    #[test]
+    fn periodic_test() {
+        assert_eq!(periodic(0, hann), vec![]);
+        assert_eq!(periodic(4, hann), vec![0.0, 0.5, 1.0, 0.5]);
+        assert_close(&periodic(4, hamming), &[0.08, 0.54, 1.0, 0.54]);
+    }
+
+    #[test]
+    fn gains_test() {
+        // known gains of periodic windows: the coherent gain is the first coefficient of
+        // the cosine sum and ENBW is 1 + sum of squares of other coefficients halved
+        for (name, window, gain, bandwidth) in [
+            ("rectangular", vec![1.0; 64], 1.0, 1.0),
+            ("hann", periodic(64, hann), 0.5, 1.5),
+            ("hamming", periodic(64, hamming), 0.54, 1.36283),
+            ("blackman", periodic(64, blackman), 0.42, 1.72676),
+            (
+                "blackman_harris",
+                periodic(64, blackman_harris),
+                0.35875,
+                2.00435,
+            ),
+        ] {
+            assert!((coherent_gain(&window) - gain).abs() < 1e-5, "{name}");
+            assert!(
+                (enbw(&window) - bandwidth).abs() < 1e-4,
+                "{name} {}",
+                enbw(&window)
+            );
+        }
+
+        // the magnitude of a sine at a bin doesn't depend on the window after normalization
+        let samples: Vec<f32> = (0..64)
+            .map(|i| (2.0 * PI * 4.0 * i as f32 / 64.0).sin())
+            .collect();
+        for window in [
+            periodic(64, hann),
+            periodic(64, blackman_harris),
+            periodic(64, flat_top),
+        ] {
+            let window = normalize(window, Normalization::Amplitude);
+            let windowed: Vec<f32> = samples.iter().zip(&window).map(|(s, w)| s * w).collect();
+            let magnitude = crate::goertzel::goertzel(&windowed, 64, 4.0);
+            assert!((magnitude - 1.0).abs() < 1e-4, "{magnitude}");
+        }
+
+        let window = normalize(periodic(64, hann), Normalization::Energy);
+        let power = window.iter().map(|w| w * w).sum::<f32>() / 64.0;
+        assert!((power - 1.0).abs() < 1e-5);
+        assert_eq!(normalize(hann(3), Normalization::None), hann(3));
+    }
+
+    #[test]
+    fn cola_test() {
+        assert!(is_cola(&[1.0; 64], 64));
+        assert!(!is_cola(&[1.0; 64], 48));
+        assert!(!is_cola(&periodic(64, hann), 64));
+        assert!(is_cola(&periodic(64, hann), 32));
+        assert!(is_cola(&periodic(64, hann), 16));
+        assert!(is_cola(&periodic(64, hamming), 32));
+        assert!(is_cola(&periodic(64, bartlett), 32));
+        assert!(is_cola(&periodic(63, blackman), 21));
+        // the symmetric window is only close to COLA
+        assert!(!is_cola(&hann(64), 32));
+    }
+
+    #[test]
+    fn coefficients_test() {
+        assert_close(&hamming(5), &[0.08, 0.54, 1.0, 0.54, 0.08]);
+        assert_close(&blackman(5), &[0.0, 0.34, 1.0, 0.34, 0.0]);
+        assert_close(
+            &blackman_harris(5),
+            &[0.00006, 0.21747, 1.0, 0.21747, 0.00006],
+        );
+        assert_close(&nuttall(5), &[0.0, 0.211536, 1.0, 0.211536, 0.0]);
+        assert_close(
+            &flat_top(5),
+            &[-0.000421, -0.05473684, 1.0, -0.05473684, -0.000421],
+        );
+        // I0(5 * sqrt(1 - t^2)) / I0(5) with I0(5) = 27.2399
+        assert_close(
+            &kaiser(5, 5.0),
+            &[0.03671089, 0.5528516, 1.0, 0.5528516, 0.03671089],
+        );
+        // Kaiser with beta 0 is rectangular
+        assert_close(&kaiser(4, 0.0), &[1.0; 4]);
+        assert_close(
+            &gaussian(5, 0.5),
+            &[0.13533528, 0.60653066, 1.0, 0.60653066, 0.13533528],
+        );
+        assert_close(
+            &tukey(9, 0.5),
+            &[0.0, 0.5, 1.0, 1.0, 1.0, 1.0, 1.0, 0.5, 0.0],
+        );
+        assert_close(&tukey(7, 1.0), &hann(7));
+        assert_close(&bartlett(5), &[0.0, 0.5, 1.0, 0.5, 0.0]);
+        assert_close(&welch(5), &[0.0, 0.75, 1.0, 0.75, 0.0]);
+    }
 }